@@ -0,0 +1,70 @@
+#import bevy_pbr::mesh_view_bind_group
+
+struct Vertex {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+#ifdef VERTEX_TANGENTS
+    [[location(3)]] tangent: vec4<f32>;
+#endif
+
+    [[location(8)]] model_x: vec4<f32>;
+    [[location(9)]] model_y: vec4<f32>;
+    [[location(10)]] model_z: vec4<f32>;
+    [[location(11)]] model_w: vec4<f32>;
+};
+
+// Same outputs as bevy_pbr's mesh.wgsl, instances are shaded by its pbr.wgsl
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec4<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+#ifdef VERTEX_TANGENTS
+    [[location(3)]] world_tangent: vec4<f32>;
+#endif
+};
+
+struct DepthVertex {
+    [[location(0)]] position: vec3<f32>;
+
+    [[location(8)]] model_x: vec4<f32>;
+    [[location(9)]] model_y: vec4<f32>;
+    [[location(10)]] model_z: vec4<f32>;
+    [[location(11)]] model_w: vec4<f32>;
+};
+
+struct DepthOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let model = mat4x4<f32>(vertex.model_x, vertex.model_y, vertex.model_z, vertex.model_w);
+
+    var out: VertexOutput;
+
+    out.world_position = model * vec4<f32>(vertex.position, 1.0);
+    // Normals assume the model is scaled uniformly
+    out.world_normal = normalize((model * vec4<f32>(vertex.normal, 0.0)).xyz);
+    out.uv = vertex.uv;
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = vec4<f32>(
+        normalize((model * vec4<f32>(vertex.tangent.xyz, 0.0)).xyz),
+        vertex.tangent.w
+    );
+#endif
+    out.clip_position = view.view_proj * out.world_position;
+
+    return out;
+}
+
+// Shadow pass, `view` is the light's view there
+[[stage(vertex)]]
+fn vertex_depth(vertex: DepthVertex) -> DepthOutput {
+    let model = mat4x4<f32>(vertex.model_x, vertex.model_y, vertex.model_z, vertex.model_w);
+
+    var out: DepthOutput;
+    out.clip_position = view.view_proj * model * vec4<f32>(vertex.position, 1.0);
+    return out;
+}
@@ -0,0 +1,20 @@
+// Appended to bevy's PBR shader by `FogPlugin`, its fragment entry point is
+// renamed `pbr_fragment` and wrapped here
+
+struct Fog {
+    // Alpha is how much fog there is, 0 while it's disabled
+    color: vec4<f32>;
+    start: f32;
+    end: f32;
+};
+
+[[group(0), binding(9)]]
+var<uniform> fog: Fog;
+
+[[stage(fragment)]]
+fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
+    let color = pbr_fragment(in);
+    let distance = length(in.world_position.xyz - view.world_position);
+    let amount = fog.color.a * clamp((distance - fog.start) / (fog.end - fog.start), 0.0, 1.0);
+    return vec4<f32>(mix(color.rgb, fog.color.rgb, amount), color.a);
+}
@@ -0,0 +1,51 @@
+struct PostProcess {
+    // 0 disables the vignette
+    vignette: f32;
+    // Fraction of the distance to the center blurred, 0 disables the blur
+    motion_blur: f32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[group(0), binding(0)]]
+var g_texture: texture_2d<f32>;
+
+[[group(0), binding(1)]]
+var g_sampler: sampler;
+
+[[group(0), binding(2)]]
+var<uniform> g_post_process: PostProcess;
+
+let BLUR_SAMPLES: i32 = 8;
+
+// Single triangle covering the screen, no vertex buffer needed
+[[stage(vertex)]]
+fn vertex([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Blur towards the center, streaks grow with the distance to it
+    let to_center = vec2<f32>(0.5, 0.5) - in.uv;
+    var color = vec3<f32>(0.0);
+    for (var i: i32 = 0; i < BLUR_SAMPLES; i = i + 1) {
+        let offset = f32(i) / f32(BLUR_SAMPLES) * g_post_process.motion_blur;
+        color = color + textureSample(g_texture, g_sampler, in.uv + to_center * offset).rgb;
+    }
+    color = color / f32(BLUR_SAMPLES);
+
+    // Distance in uv space, 1 in the corners so the vignette follows the screen's shape
+    let distance = length(to_center) * 1.41421356;
+    color = color * (1.0 - g_post_process.vignette * smoothstep(0.4, 1.0, distance));
+
+    return vec4<f32>(color, 1.0);
+}
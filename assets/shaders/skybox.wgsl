@@ -13,29 +13,84 @@ struct Vertex {
 
 struct VertexOutput {
     [[builtin(position)]] position: vec4<f32>;
-    [[location(0)]] uv: vec3<f32>;
+    [[location(0)]] world_direction: vec3<f32>;
 };
 
+struct Fog {
+    // Alpha is how much fog there is, 0 while it's disabled
+    color: vec4<f32>;
+    start: f32;
+    end: f32;
+};
+
+[[group(0), binding(9)]]
+var<uniform> fog: Fog;
+
 [[group(1), binding(0)]]
 var<uniform> g_mesh: Mesh;
 
+// Height above the horizon the sky fades into the fog until
+let HAZE_HEIGHT: f32 = 0.08;
+
+#ifdef ATMOSPHERE
+struct Atmosphere {
+    sun_direction: vec3<f32>;
+    sun_intensity: f32;
+    moon_direction: vec3<f32>;
+    star_brightness: f32;
+    tint: vec4<f32>;
+    exposure: f32;
+};
+
+[[group(2), binding(0)]]
+var<uniform> atmosphere: Atmosphere;
+#else
+struct SkyboxTexture {
+    // Inverse of the sky rotation
+    rotation: mat4x4<f32>;
+    tint: vec4<f32>;
+    exposure: f32;
+    // 0 shows the day texture, 1 the night one
+    blend: f32;
+};
+
 [[group(2), binding(0)]]
 var g_texture: texture_2d_array<f32>;
 
 [[group(2), binding(1)]]
+var g_night_texture: texture_2d_array<f32>;
+
+[[group(2), binding(2)]]
 var g_sampler: sampler;
 
+[[group(2), binding(3)]]
+var<uniform> g_skybox: SkyboxTexture;
+#endif
+
 [[stage(vertex)]]
 fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
     out.position = vec4<f32>(vertex.position, 1.0);
     out.position.z = 0.0;
-    out.uv = vertex.position;
+    // Ray through this clip position, from view space to world space
+    let view_direction = vec3<f32>(
+        vertex.position.x / view.projection[0][0],
+        vertex.position.y / view.projection[1][1],
+        -1.0,
+    );
+    out.world_direction = (view.view * vec4<f32>(view_direction, 0.0)).xyz;
 
     return out;
 }
 
+// Fade the sky into the fog towards the horizon, where fogged models meet it
+fn haze(color: vec3<f32>, world_direction: vec3<f32>) -> vec3<f32> {
+    let height = normalize(world_direction).y;
+    let amount = fog.color.a * (1.0 - smoothstep(0.0, HAZE_HEIGHT, height));
+    return mix(color, fog.color.rgb, amount);
+}
+
 struct Face {
     uv: vec2<f32>;
     idx: i32;
@@ -69,11 +124,104 @@ fn face(ray: vec3<f32>) -> Face {
     return out;
 }
 
+#ifdef ATMOSPHERE
+let PI: f32 = 3.141592653589793;
+// Scattering coefficients at sea level, per metre
+let RAYLEIGH: vec3<f32> = vec3<f32>(5.8e-6, 13.5e-6, 33.1e-6);
+let MIE: f32 = 21.0e-6;
+// Scale heights, in metres
+let RAYLEIGH_HEIGHT: f32 = 8.0e3;
+let MIE_HEIGHT: f32 = 1.2e3;
+let MIE_G: f32 = 0.76;
+
+fn rayleigh_phase(mu: f32) -> f32 {
+    return 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+}
+
+// Cornette-Shanks
+fn mie_phase(mu: f32) -> f32 {
+    let g2 = MIE_G * MIE_G;
+    return 3.0 / (8.0 * PI) * ((1.0 - g2) * (1.0 + mu * mu))
+        / ((2.0 + g2) * pow(1.0 + g2 - 2.0 * MIE_G * mu, 1.5));
+}
+
+// Relative thickness of air crossed looking at `height`, 1 at the zenith
+fn air_mass(height: f32) -> f32 {
+    let zenith = acos(clamp(height, 0.0, 1.0));
+    return 1.0 / (cos(zenith) + 0.15 * pow(93.885 - degrees(zenith), -1.253));
+}
+
+// Single scattering of light coming from `light` seen along `ray`
+fn scattering(ray: vec3<f32>, light: vec3<f32>, intensity: f32) -> vec3<f32> {
+    let mu = dot(ray, light);
+    let rayleigh_depth = RAYLEIGH * RAYLEIGH_HEIGHT;
+    let mie_depth = vec3<f32>(MIE * MIE_HEIGHT);
+
+    // Light reaching the scattering air, then the camera
+    let sun_extinction = exp(-(rayleigh_depth + mie_depth) * air_mass(light.y));
+    let view_extinction = exp(-(rayleigh_depth + mie_depth) * air_mass(ray.y));
+
+    let in_scatter = (RAYLEIGH * rayleigh_phase(mu) + vec3<f32>(MIE * mie_phase(mu)))
+        / (RAYLEIGH + vec3<f32>(MIE));
+    // Fades the light out as it sets instead of cutting at the horizon
+    let horizon = smoothstep(-0.1, 0.05, light.y);
+
+    return intensity * horizon * in_scatter * sun_extinction * (1.0 - view_extinction);
+}
+
+fn hash(p: vec3<f32>) -> f32 {
+    return fract(sin(dot(p, vec3<f32>(12.9898, 78.233, 37.719))) * 43758.5453);
+}
+
+fn stars(ray: vec3<f32>) -> f32 {
+    let cell = floor(ray * 300.0);
+    let seed = hash(cell);
+    // Twinkle-free, only a few cells hold a star
+    return step(0.997, seed) * (seed - 0.997) / 0.003;
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let ray = normalize(in.world_direction);
+    let sun = atmosphere.sun_direction;
+    let moon = atmosphere.moon_direction;
+
+    var color = scattering(ray, sun, atmosphere.sun_intensity)
+        // The moon reflects a fraction of the sunlight
+        + scattering(ray, moon, atmosphere.sun_intensity * 0.02);
+
+    let view_extinction = exp(-(RAYLEIGH * RAYLEIGH_HEIGHT + vec3<f32>(MIE * MIE_HEIGHT))
+        * air_mass(ray.y));
+    let above_horizon = smoothstep(-0.02, 0.02, ray.y);
+    if (dot(ray, sun) > 0.9998) {
+        color = color + atmosphere.sun_intensity * view_extinction * above_horizon;
+    }
+    if (dot(ray, moon) > 0.9996) {
+        color = color + vec3<f32>(0.9, 0.9, 0.8) * view_extinction * above_horizon;
+    }
+
+    let night = 1.0 - smoothstep(-0.2, 0.1, sun.y);
+    color = color
+        + vec3<f32>(stars(ray) * atmosphere.star_brightness * night * above_horizon)
+        * view_extinction;
+
+    // Simple tone mapping, the scattering is in HDR units
+    color = vec3<f32>(1.0) - exp(-color * atmosphere.exposure);
+    color = color * atmosphere.tint.rgb;
+
+    return vec4<f32>(haze(color, in.world_direction), 1.0);
+}
+#else
 [[stage(fragment)]]
 fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let face_info = face(in.uv);
+    let ray = (g_skybox.rotation * vec4<f32>(in.world_direction, 0.0)).xyz;
+    let face_info = face(ray);
 
-    var color = textureSample(g_texture, g_sampler, face_info.uv, face_info.idx);
+    let day = textureSample(g_texture, g_sampler, face_info.uv, face_info.idx);
+    let night = textureSample(g_night_texture, g_sampler, face_info.uv, face_info.idx);
+    let color = mix(day, night, g_skybox.blend);
 
-    return color;
+    let sky = color.rgb * g_skybox.tint.rgb * g_skybox.exposure;
+    return vec4<f32>(haze(sky, in.world_direction), color.a);
 }
+#endif
@@ -0,0 +1,74 @@
+//! Renders top-down maps of generated tracks without opening a window.
+//!
+//! Usage: `level-preview <seed | first..last> [--from Z] [--to Z] [--out DIR] [--ascii]`
+
+use race_the_moon::level::difficulty::{DifficultyCurve, DIFFICULTY_CURVE_PATH};
+use race_the_moon::level::preview::TrackPreview;
+use std::path::PathBuf;
+use std::process::exit;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(seeds) = args.first().and_then(|arg| parse_seeds(arg)) else {
+        eprintln!(
+            "Usage: level-preview <seed | first..last> [--from Z] [--to Z] [--out DIR] [--ascii]"
+        );
+        exit(2);
+    };
+
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let from = option("--from")
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(0.0);
+    let to = option("--to")
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(10000.0);
+    let out = PathBuf::from(option("--out").map_or("previews", String::as_str));
+    let ascii = args.iter().any(|arg| arg == "--ascii");
+
+    if let Err(err) = std::fs::create_dir_all(&out) {
+        eprintln!("Failed to create output directory {:?}: {}", out, err);
+        exit(1);
+    }
+
+    let curve = DifficultyCurve::from_path_or_default(DIFFICULTY_CURVE_PATH);
+    for seed in seeds {
+        let preview = TrackPreview::generate(seed, from, to, &curve);
+
+        let result = if ascii {
+            let path = out.join(format!("seed_{}.txt", seed));
+            std::fs::write(&path, preview.to_ascii())
+                .map(|_| path)
+                .map_err(|err| err.to_string())
+        } else {
+            let path = out.join(format!("seed_{}.png", seed));
+            preview
+                .to_image()
+                .save(&path)
+                .map(|_| path)
+                .map_err(|err| err.to_string())
+        };
+
+        match result {
+            Ok(path) => println!("seed {}: {:?}", seed, path),
+            Err(err) => {
+                eprintln!("seed {}: {}", seed, err);
+                exit(1);
+            }
+        }
+    }
+}
+
+fn parse_seeds(arg: &str) -> Option<std::ops::RangeInclusive<u64>> {
+    match arg.split_once("..") {
+        Some((first, last)) => Some(first.parse().ok()?..=last.parse().ok()?),
+        None => {
+            let seed = arg.parse().ok()?;
+            Some(seed..=seed)
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Checks every model and manifest under the assets directory.
+//!
+//! Usage: `validate-assets [ASSETS_DIR]`
+
+use race_the_moon::utils::asset_validation::validate_assets;
+use std::path::PathBuf;
+use std::process::exit;
+
+fn main() {
+    let root = PathBuf::from(std::env::args().nth(1).as_deref().unwrap_or("assets"));
+    if !root.is_dir() {
+        eprintln!("Usage: validate-assets [ASSETS_DIR]");
+        exit(2);
+    }
+
+    let problems = validate_assets(&root);
+    for problem in problems.iter() {
+        eprintln!("{}", problem);
+    }
+
+    if problems.is_empty() {
+        println!("All assets in {:?} are valid", root);
+    } else {
+        eprintln!("{} problems found in {:?}", problems.len(), root);
+        exit(1);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::editor::{EditorObject, EditorState};
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+
+const HANDLE_LENGTH: f32 = 8.0;
+const HANDLE_THICKNESS: f32 = 0.4;
+const SCALE_SENSITIVITY: f32 = 0.01;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GizmoMode {
+    Translate,
+    Scale,
+}
+
+#[derive(Component)]
+pub struct GizmoMarker;
+
+/// Visibility isn't propagated to children, so every handle is toggled on its own
+#[derive(Component)]
+pub struct GizmoHandleMarker;
+
+impl Default for GizmoMode {
+    fn default() -> Self {
+        GizmoMode::Translate
+    }
+}
+
+pub fn spawn_gizmo(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let handles = [
+        (Vec3::X, Color::RED),
+        (Vec3::Y, Color::GREEN),
+        (Vec3::Z, Color::BLUE),
+    ];
+
+    commands
+        .spawn_bundle((
+            Transform::default(),
+            GlobalTransform::default(),
+            GizmoMarker,
+        ))
+        .with_children(|parent| {
+            for (axis, color) in handles {
+                let size = Vec3::splat(HANDLE_THICKNESS) + axis * HANDLE_LENGTH;
+                parent
+                    .spawn_bundle(PbrBundle {
+                        mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+                        material: materials.add(StandardMaterial {
+                            base_color: color,
+                            unlit: true,
+                            ..Default::default()
+                        }),
+                        transform: Transform::from_translation(axis * HANDLE_LENGTH / 2.0),
+                        visibility: Visibility { is_visible: false },
+                        ..Default::default()
+                    })
+                    .insert(GizmoHandleMarker);
+            }
+        });
+}
+
+pub fn follow_selected_object(
+    state: Res<EditorState>,
+    q_objects: Query<&Transform, (With<EditorObject>, Without<GizmoMarker>)>,
+    mut q_gizmo: Query<&mut Transform, With<GizmoMarker>>,
+    mut q_handles: Query<&mut Visibility, With<GizmoHandleMarker>>,
+) {
+    let Ok(mut gizmo) = q_gizmo.get_single_mut() else {
+        return;
+    };
+
+    let selected = state.active_selection().and_then(|e| q_objects.get(e).ok());
+    if let Some(selected) = selected {
+        gizmo.translation = selected.translation;
+    }
+
+    for mut visibility in q_handles.iter_mut() {
+        visibility.is_visible = selected.is_some();
+    }
+}
+
+/// Scale mode gizmo, dragging the mouse up or down while holding the left
+/// button uniformly scales the selected object
+pub fn drag_scale(
+    state: Res<EditorState>,
+    mouse_button: Res<Input<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut q_objects: Query<&mut EditorObject>,
+) {
+    let delta: f32 = mouse_motion.iter().map(|event| event.delta.y).sum();
+    if state.gizmo_mode != GizmoMode::Scale || !mouse_button.pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(mut object) = state
+        .active_selection()
+        .and_then(|e| q_objects.get_mut(e).ok())
+    else {
+        return;
+    };
+
+    let factor = (1.0 - delta * SCALE_SENSITIVITY).max(0.01);
+    object.scale *= factor;
+}
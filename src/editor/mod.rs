@@ -0,0 +1,401 @@
+use crate::editor::gizmo::GizmoMode;
+use crate::editor::picking::{cursor_ray, intersect_ground};
+use crate::level::file::{LevelFile, LevelObject};
+use crate::utils::local_settings::{Action, LocalSettingsLoader};
+use crate::utils::manifest::ManifestMaterial;
+use crate::utils::rotate_camera_with_mouse;
+use crate::utils::spawn::{self, ManifestApplied};
+use crate::MainCameraMarker;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::RapierConfiguration;
+use std::fs;
+
+pub mod gizmo;
+pub mod picking;
+
+/// Objects closer than this to the clicked point get selected
+const PICK_RADIUS: f32 = 4.0;
+const CAMERA_SPEED: f32 = 100.0;
+const CAMERA_WHEEL_STEP: f32 = 10.0;
+
+pub struct EditorPlugin {
+    pub level_path: String,
+}
+
+#[derive(Default)]
+pub struct EditorState {
+    pub active: bool,
+    level_path: String,
+    palette: Vec<String>,
+    palette_index: usize,
+    selected_object: Option<Entity>,
+    gizmo_mode: GizmoMode,
+    game_title: String,
+}
+
+/// Object placed in the editor, its final transform is the one from the
+/// model's manifest with `translation` and `scale` applied on top
+#[derive(Component)]
+pub struct EditorObject {
+    pub model: String,
+    pub translation: Vec3,
+    pub scale: Vec3,
+    /// Kept from the level file, there is no material editing yet
+    pub material: Option<ManifestMaterial>,
+}
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EditorState {
+            level_path: self.level_path.clone(),
+            ..Default::default()
+        })
+        .add_startup_system(gizmo::spawn_gizmo)
+        .add_startup_system(load_level)
+        .add_system(toggle_editor)
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(is_editing)
+                .with_system(fly_editor_camera)
+                .with_system(rotate_camera_with_mouse)
+                .with_system(cycle_palette)
+                .with_system(select_gizmo_mode)
+                .with_system(select_or_drag)
+                .with_system(place_object)
+                .with_system(gizmo::drag_scale)
+                .with_system(delete_selected)
+                .with_system(save_level)
+                .with_system(update_window_title),
+        )
+        .add_system(apply_editor_object_transform)
+        .add_system(gizmo::follow_selected_object);
+    }
+}
+
+impl EditorState {
+    pub fn active_selection(&self) -> Option<Entity> {
+        self.selected_object.filter(|_| self.active)
+    }
+
+    fn selected_model(&self) -> Option<&String> {
+        self.palette.get(self.palette_index)
+    }
+}
+
+pub fn is_editing(state: Res<EditorState>) -> ShouldRun {
+    if state.active {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+pub fn not_editing(state: Res<EditorState>) -> ShouldRun {
+    if state.active {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+/// Every `.gltf` in `assets/models`, as asset paths
+fn collect_palette() -> Vec<String> {
+    let entries = match fs::read_dir("assets/models") {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Failed to read models directory: {}", err);
+            return Vec::new();
+        }
+    };
+
+    let mut palette: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "gltf"))
+        .filter_map(|path| {
+            path.file_name()
+                .map(|name| format!("models/{}", name.to_string_lossy()))
+        })
+        .collect();
+    palette.sort();
+    palette
+}
+
+/// Objects of the saved level are part of the game, the editor changes them in place
+pub fn load_level(mut commands: Commands, asset_server: Res<AssetServer>, state: Res<EditorState>) {
+    let Some(level) = LevelFile::from_path(&state.level_path) else {
+        return;
+    };
+
+    for object in level.objects.iter() {
+        spawn_editor_object(
+            &mut commands,
+            asset_server.as_ref(),
+            &object.model,
+            object.translation.into(),
+            object.scale.into(),
+            object.material.clone(),
+        );
+    }
+}
+
+pub fn toggle_editor(
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    mut state: ResMut<EditorState>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut windows: ResMut<Windows>,
+) {
+    if !input.just_pressed(local_settings.key(Action::ToggleEditor)) {
+        return;
+    }
+
+    state.active = !state.active;
+    rapier_configuration.physics_pipeline_active = !state.active;
+
+    if state.active {
+        state.palette = collect_palette();
+        state.palette_index = 0;
+        state.selected_object = None;
+        if let Some(window) = windows.get_primary() {
+            state.game_title = window.title().to_string();
+        }
+    } else if let Some(window) = windows.get_primary_mut() {
+        window.set_title(state.game_title.clone());
+    }
+}
+
+fn spawn_editor_object(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    model: &str,
+    translation: Vec3,
+    scale: Vec3,
+    material: Option<ManifestMaterial>,
+) -> Entity {
+    let entity = match material.clone() {
+        Some(level_material) => {
+            spawn::spawn_model_override(model, commands, asset_server, move |manifest| {
+                manifest.material = Some(match &manifest.material {
+                    Some(material) => material.overridden_by(&level_material),
+                    None => level_material.clone(),
+                });
+            })
+        }
+        None => spawn::spawn_model(model, commands, asset_server),
+    };
+
+    commands.entity(entity).insert(EditorObject {
+        model: model.to_string(),
+        translation,
+        scale,
+        material,
+    });
+
+    entity
+}
+
+/// Place objects relative to their manifest transform, also after the manifest is reloaded
+#[allow(clippy::type_complexity)]
+pub fn apply_editor_object_transform(
+    mut q_objects: Query<
+        (&mut Transform, &EditorObject, &ManifestApplied),
+        Or<(Changed<EditorObject>, Changed<ManifestApplied>)>,
+    >,
+) {
+    for (mut transform, object, manifest) in q_objects.iter_mut() {
+        transform.translation = manifest.base.translation + object.translation;
+        transform.scale = manifest.base.scale * object.scale;
+    }
+}
+
+pub fn fly_editor_camera(
+    time: Res<Time>,
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut q_camera: Query<&mut Transform, With<MainCameraMarker>>,
+) {
+    let Ok(mut camera) = q_camera.get_single_mut() else {
+        return;
+    };
+
+    let forward = camera.forward();
+    let right = camera.right();
+
+    for event in mouse_wheel.iter() {
+        camera.translation += forward * event.y.signum() * CAMERA_WHEEL_STEP;
+    }
+
+    let mut direction = Vec3::ZERO;
+    for (action, axis) in [
+        (Action::Left, -right),
+        (Action::Right, right),
+        (Action::EditorForward, forward),
+        (Action::EditorBackward, -forward),
+        (Action::EditorUp, Vec3::Y),
+        (Action::EditorDown, -Vec3::Y),
+    ] {
+        if input.pressed(local_settings.key(action)) {
+            direction += axis;
+        }
+    }
+    camera.translation += direction * CAMERA_SPEED * time.delta().as_secs_f32();
+}
+
+pub fn cycle_palette(
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    mut state: ResMut<EditorState>,
+) {
+    if input.just_pressed(local_settings.key(Action::EditorNextModel)) && !state.palette.is_empty()
+    {
+        state.palette_index = (state.palette_index + 1) % state.palette.len();
+    }
+}
+
+pub fn select_gizmo_mode(
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    mut state: ResMut<EditorState>,
+) {
+    if input.just_pressed(local_settings.key(Action::EditorTranslate)) {
+        state.gizmo_mode = GizmoMode::Translate;
+    } else if input.just_pressed(local_settings.key(Action::EditorScale)) {
+        state.gizmo_mode = GizmoMode::Scale;
+    }
+}
+
+/// Left click selects the object under the cursor, or deselects when there is
+/// none. In translate mode, holding the button drags the selection along the ground
+pub fn select_or_drag(
+    mouse_button: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    mut state: ResMut<EditorState>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCameraMarker>>,
+    mut q_objects: Query<(Entity, &Transform, &ManifestApplied, &mut EditorObject)>,
+) {
+    let Ok((camera, camera_transform)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(hit) = cursor_ray(&windows, camera, camera_transform).and_then(intersect_ground)
+    else {
+        return;
+    };
+
+    if mouse_button.just_pressed(MouseButton::Left) {
+        let closest = q_objects
+            .iter()
+            .map(|(entity, transform, _, _)| (entity, transform.translation.distance(hit)))
+            .filter(|(_, distance)| *distance < PICK_RADIUS)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        state.selected_object = closest.map(|(entity, _)| entity);
+    } else if mouse_button.pressed(MouseButton::Left) && state.gizmo_mode == GizmoMode::Translate {
+        let Some((_, _, manifest, mut object)) = state
+            .selected_object
+            .and_then(|e| q_objects.get_mut(e).ok())
+        else {
+            return;
+        };
+
+        object.translation.x = hit.x - manifest.base.translation.x;
+        object.translation.z = hit.z - manifest.base.translation.z;
+    }
+}
+
+/// Place the current palette model on the ground under the cursor and select it
+#[allow(clippy::too_many_arguments)]
+pub fn place_object(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    mut state: ResMut<EditorState>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCameraMarker>>,
+) {
+    if !input.just_pressed(local_settings.key(Action::EditorPlace)) {
+        return;
+    }
+    let Some(model) = state.selected_model().cloned() else {
+        return;
+    };
+    let Ok((camera, camera_transform)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(hit) = cursor_ray(&windows, camera, camera_transform).and_then(intersect_ground)
+    else {
+        return;
+    };
+
+    state.selected_object = Some(spawn_editor_object(
+        &mut commands,
+        asset_server.as_ref(),
+        &model,
+        Vec3::new(hit.x, 0.0, hit.z),
+        Vec3::ONE,
+        None,
+    ));
+}
+
+pub fn delete_selected(
+    mut commands: Commands,
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    mut state: ResMut<EditorState>,
+) {
+    if !input.just_pressed(local_settings.key(Action::EditorDelete)) {
+        return;
+    }
+    if let Some(entity) = state.selected_object.take() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn save_level(
+    local_settings: Res<LocalSettingsLoader>,
+    input: Res<Input<KeyCode>>,
+    state: Res<EditorState>,
+    q_objects: Query<&EditorObject>,
+) {
+    if !input.just_pressed(local_settings.key(Action::EditorSave)) {
+        return;
+    }
+
+    let level = LevelFile {
+        objects: q_objects
+            .iter()
+            .map(|object| LevelObject {
+                model: object.model.clone(),
+                translation: object.translation.into(),
+                scale: object.scale.into(),
+                material: object.material.clone(),
+            })
+            .collect(),
+    };
+    level.save(&state.level_path);
+}
+
+/// There is no font to draw UI with, so the editor status lives in the window title
+pub fn update_window_title(state: Res<EditorState>, mut windows: ResMut<Windows>) {
+    // The editor may have been closed this frame, after run criteria were evaluated
+    if !state.active {
+        return;
+    }
+    let Some(window) = windows.get_primary_mut() else {
+        return;
+    };
+
+    let title = format!(
+        "Editor - {} - model: {} - mode: {:?}",
+        state.level_path,
+        state.selected_model().map_or("<none>", String::as_str),
+        state.gizmo_mode,
+    );
+    if window.title() != title {
+        window.set_title(title);
+    }
+}
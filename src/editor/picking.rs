@@ -0,0 +1,35 @@
+use crate::entities::environment::GROUND_HEIGHT;
+use bevy::prelude::*;
+
+/// Ray from the camera through the cursor, as `(origin, direction)`
+pub fn cursor_ray(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<(Vec3, Vec3)> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+
+    let ndc = cursor / Vec2::new(window.width(), window.height()) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
+
+    // Projection uses reversed infinite depth, near plane is at 1.0 and far plane at 0.0
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(f32::EPSILON));
+
+    Some((near, (far - near).normalize()))
+}
+
+/// Point where the ray hits the ground plane, if it does
+pub fn intersect_ground((origin, direction): (Vec3, Vec3)) -> Option<Vec3> {
+    if direction.y.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let t = (GROUND_HEIGHT - origin.y) / direction.y;
+    if t < 0.0 {
+        return None;
+    }
+
+    Some(origin + direction * t)
+}
@@ -1,13 +1,17 @@
+use crate::editor::not_editing;
 use crate::entities::ship::{PlayerShipMarker, LABEL_FLY_SHIP};
-use crate::{App, MainCameraMarker};
+use crate::MainCameraMarker;
 use bevy::prelude::*;
 
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup_main_camera)
-            .add_system(camera_follow_spaceship.after(LABEL_FLY_SHIP));
+        app.add_startup_system(setup_main_camera).add_system(
+            camera_follow_spaceship
+                .after(LABEL_FLY_SHIP)
+                .with_run_criteria(not_editing),
+        );
     }
 }
 
@@ -1,3 +1,4 @@
 pub mod camera;
 pub mod environment;
 pub mod ship;
+pub mod track;
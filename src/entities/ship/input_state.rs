@@ -1,3 +1,4 @@
+#[derive(Clone, Copy)]
 pub enum TurnDirection {
     Left = -1,
     Right = 1,
@@ -15,3 +16,9 @@ impl Default for TurnDirection {
         TurnDirection::None
     }
 }
+
+impl From<TurnDirection> for f32 {
+    fn from(direction: TurnDirection) -> Self {
+        direction as i32 as f32
+    }
+}
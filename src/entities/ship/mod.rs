@@ -1,3 +1,5 @@
+use crate::editor::not_editing;
+use crate::level::difficulty::DifficultyCurve;
 use crate::utils::local_settings::{Action, LocalSettingsLoader};
 use crate::utils::spawn::spawn_model_as_child;
 use bevy::prelude::*;
@@ -7,13 +9,18 @@ use input_state::{PlayerShipInputState, TurnDirection};
 
 mod input_state;
 
-const TERMINAL_VELOCITY_X: f32 = 100.0;
-const TERMINAL_VELOCITY_Z: &[f32] = &[120.0, 240.0, 350.0, 420.0, 500.0, 560.0, 600.0];
+pub const TERMINAL_VELOCITY_X: f32 = 100.0;
+pub const TERMINAL_VELOCITY_Z: &[f32] = &[120.0, 240.0, 350.0, 420.0, 500.0, 560.0, 600.0];
 
-const DAMPING_X: f32 = 150.0;
+pub const DAMPING_X: f32 = 150.0;
+/// Rapier damping applied to the whole linear velocity of the ship body
+pub const LINEAR_DAMPING: f32 = 20.0;
 
-const FORCE_X: f32 = 2000.0;
-const FORCE_Z: f32 = 10000.0;
+pub const FORCE_X: f32 = 2000.0;
+pub const FORCE_Z: f32 = 10000.0;
+
+/// Half extents of the ship collider
+pub const SHIP_HALF_EXTENTS: f32 = 1.0;
 
 const LABEL_HANDLE_USER_INPUT: &str = "a3abb244-887a-469d-8a34-f6c154b0d310";
 pub const LABEL_FLY_SHIP: &str = "af0a465f-99e8-4023-bcc1-921ff9a1e00a";
@@ -37,7 +44,8 @@ impl Plugin for ShipControlPlugin {
             .add_system(
                 fly_ship
                     .label(LABEL_FLY_SHIP)
-                    .after(LABEL_HANDLE_USER_INPUT),
+                    .after(LABEL_HANDLE_USER_INPUT)
+                    .with_run_criteria(not_editing),
             );
     }
 }
@@ -53,7 +61,7 @@ pub fn spawn_player_ship(
         .insert_bundle(RigidBodyBundle {
             body_type: RigidBodyType::Dynamic.into(),
             damping: RigidBodyDamping {
-                linear_damping: 20.0,
+                linear_damping: LINEAR_DAMPING,
                 ..Default::default()
             }
             .into(),
@@ -65,7 +73,8 @@ pub fn spawn_player_ship(
             ..Default::default()
         })
         .insert_bundle(ColliderBundle {
-            shape: ColliderShape::cuboid(1.0, 1.0, 1.0).into(),
+            shape: ColliderShape::cuboid(SHIP_HALF_EXTENTS, SHIP_HALF_EXTENTS, SHIP_HALF_EXTENTS)
+                .into(),
             ..Default::default()
         })
         .insert(Transform::default())
@@ -84,11 +93,12 @@ pub fn spawn_player_ship(
 
 pub fn fly_ship(
     time: Res<Time>,
-    mut q_spaceship: Query<(&mut RigidBodyVelocityComponent,), With<PlayerShipMarker>>,
+    mut q_spaceship: Query<(&mut RigidBodyVelocityComponent, &Transform), With<PlayerShipMarker>>,
     mut descriptor: ResMut<PlayerShipDescriptor>,
     state: Res<PlayerShipInputState>,
+    difficulty_curve: Res<DifficultyCurve>,
 ) {
-    let Ok((mut vel, )) = q_spaceship.get_single_mut() else {
+    let Ok((mut vel, transform)) = q_spaceship.get_single_mut() else {
         return
     };
 
@@ -96,30 +106,40 @@ pub fn fly_ship(
         descriptor.speed_level += 1;
     }
 
-    let dt = time.delta().as_secs_f32();
-
-    vel.linvel.x = vel.linvel.x - vel.linvel.x.signum() * DAMPING_X * dt;
+    let min_speed_level = difficulty_curve
+        .sample(transform.translation.z)
+        .min_speed_level;
+    descriptor.speed_level = descriptor.speed_level.max(min_speed_level);
 
-    let target_x_force = match state.turning {
-        TurnDirection::Left => -FORCE_X,
-        TurnDirection::Right => FORCE_X,
-        TurnDirection::None => 0.0,
-    };
+    let dt = time.delta().as_secs_f32();
 
-    vel.linvel.x =
-        (vel.linvel.x + target_x_force * dt).clamp(-TERMINAL_VELOCITY_X, TERMINAL_VELOCITY_X);
-    if vel.linvel.x.abs() < 5.0 {
-        vel.linvel.x = 0.0;
-    }
+    vel.linvel.x = lateral_velocity_step(vel.linvel.x, state.turning.into(), dt);
     vel.linvel.z += FORCE_Z * dt;
-    let terminal_velocity_z =
-        TERMINAL_VELOCITY_Z[descriptor.speed_level.min(TERMINAL_VELOCITY_Z.len() - 1)];
+    let terminal_velocity_z = terminal_velocity_z(descriptor.speed_level);
     vel.linvel.z = vel
         .linvel
         .z
         .clamp(-terminal_velocity_z, terminal_velocity_z);
 }
 
+/// New lateral velocity of the ship after `dt` with `direction` being -1, 0
+/// or 1. Shared with the level navigability checker so both agree on how the
+/// ship handles
+pub fn lateral_velocity_step(velocity_x: f32, direction: f32, dt: f32) -> f32 {
+    let mut velocity_x = velocity_x - velocity_x.signum() * DAMPING_X * dt;
+
+    velocity_x =
+        (velocity_x + direction * FORCE_X * dt).clamp(-TERMINAL_VELOCITY_X, TERMINAL_VELOCITY_X);
+    if velocity_x.abs() < 5.0 {
+        velocity_x = 0.0;
+    }
+    velocity_x
+}
+
+pub fn terminal_velocity_z(speed_level: usize) -> f32 {
+    TERMINAL_VELOCITY_Z[speed_level.min(TERMINAL_VELOCITY_Z.len() - 1)]
+}
+
 pub fn handle_user_input(
     local_settings: Res<LocalSettingsLoader>,
     input: Res<Input<KeyCode>>,
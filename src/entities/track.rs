@@ -0,0 +1,225 @@
+use crate::editor::not_editing;
+use crate::entities::ship::PlayerShipMarker;
+use crate::level::difficulty::DifficultyCurve;
+use crate::level::generator::{Chunk, Obstacle, ObstacleKind, TrackGenerator};
+use crate::level::navigability::{clear_chunk, ensure_navigable, start_states, ShipState};
+use crate::utils::alter_transform_once::{AlterTransformOnce, TransformTween, TweenStart};
+use crate::utils::manifest::{ManifestBodyType, ManifestComponent, ManifestObstacleBehaviour};
+use crate::utils::spawn::{spawn_model_override, ManifestApplied, ManifestComponents};
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy_easings::{EaseFunction, EasingComponent};
+use futures_lite::future;
+use std::time::Duration;
+
+/// How far ahead of the ship chunks are generated
+const SPAWN_AHEAD: f32 = 2500.0;
+/// How far behind the ship obstacles are removed
+const DESPAWN_BEHIND: f32 = 100.0;
+/// How deep below their target obstacles start rising from
+const OBSTACLE_RISE_DEPTH: f32 = 30.0;
+const OBSTACLE_RISE_DURATION: Duration = Duration::from_millis(800);
+const OBSTACLE_MODEL: &str = "models/pillar.gltf";
+
+pub struct TrackPlugin {
+    pub seed: u64,
+    pub difficulty_curve: String,
+}
+
+pub struct TrackState {
+    /// Handed to `pending` while it generates the next chunk
+    stream: Option<TrackStream>,
+    pending: Option<Task<(TrackStream, Chunk)>>,
+}
+
+/// Chunks are generated one at a time so the track only depends on the seed
+struct TrackStream {
+    generator: TrackGenerator,
+    /// States the ship can reach at the end of the last generated chunk
+    entry_states: Vec<ShipState>,
+}
+
+#[derive(Component)]
+pub struct TrackObstacleMarker;
+
+/// Obstacle that eases up into place once its manifest is applied
+#[derive(Component)]
+pub struct RiseFromGround;
+
+/// Swings along x around the transform from its manifest
+#[derive(Component)]
+pub struct MovingObstacle {
+    pub amplitude: f32,
+    pub period: f32,
+}
+
+impl Plugin for TrackPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DifficultyCurve::from_path_or_default(
+            &self.difficulty_curve,
+        ))
+        .insert_resource(TrackState {
+            stream: Some(TrackStream {
+                generator: TrackGenerator::new(self.seed),
+                entry_states: start_states(),
+            }),
+            pending: None,
+        })
+        .add_system(stream_track.with_run_criteria(not_editing))
+        .add_system(insert_manifest_obstacles)
+        .add_system(rise_obstacles)
+        .add_system(move_obstacles);
+    }
+}
+
+impl TrackStream {
+    /// Generate the next chunk and repair it until it can be flown through
+    fn next_chunk(&mut self, curve: &DifficultyCurve) -> Chunk {
+        let mut chunk = self.generator.next_chunk(curve);
+        let path = match ensure_navigable(&mut chunk, curve, &self.entry_states) {
+            Ok((path, repaired)) => {
+                if !repaired.is_empty() {
+                    debug!(
+                        "Repaired {} unbeatable rows in chunk at {}",
+                        repaired.len(),
+                        chunk.start_z
+                    );
+                }
+                path
+            }
+            Err(err) => {
+                error!("{}, removing its obstacles", err);
+                clear_chunk(&mut chunk, &self.entry_states)
+            }
+        };
+        self.entry_states = path.exit_states;
+        chunk
+    }
+}
+
+/// Chunks are generated and checked on the async compute pool, their
+/// obstacles are spawned once they're ready
+pub fn stream_track(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    task_pool: Res<AsyncComputeTaskPool>,
+    curve: Res<DifficultyCurve>,
+    mut state: ResMut<TrackState>,
+    q_spaceship: Query<&Transform, With<PlayerShipMarker>>,
+    q_obstacles: Query<(Entity, &GlobalTransform), With<TrackObstacleMarker>>,
+) {
+    let Ok(spaceship) = q_spaceship.get_single() else {
+        return;
+    };
+    let distance = spaceship.translation.z;
+
+    if let Some(mut task) = state.pending.take() {
+        match future::block_on(future::poll_once(&mut task)) {
+            Some((stream, chunk)) => {
+                state.stream = Some(stream);
+                for obstacle in chunk.obstacles.iter() {
+                    spawn_obstacle(&mut commands, asset_server.as_ref(), obstacle);
+                }
+            }
+            None => state.pending = Some(task),
+        }
+    }
+
+    let needs_chunk = matches!(
+        &state.stream,
+        Some(stream) if stream.generator.next_z() < distance + SPAWN_AHEAD
+    );
+    if needs_chunk {
+        let mut stream = state.stream.take().unwrap();
+        let curve = curve.clone();
+        state.pending = Some(task_pool.spawn(async move {
+            let chunk = stream.next_chunk(&curve);
+            (stream, chunk)
+        }));
+    }
+
+    for (entity, transform) in q_obstacles.iter() {
+        if transform.translation.z < distance - DESPAWN_BEHIND {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Turn models declared as obstacles by their manifest into track obstacles
+pub fn insert_manifest_obstacles(
+    mut commands: Commands,
+    q_models: Query<(Entity, &ManifestComponents), Added<ManifestComponents>>,
+) {
+    for (entity, components) in q_models.iter() {
+        for component in components.0.iter() {
+            let ManifestComponent::Obstacle { behaviour } = component else {
+                continue;
+            };
+            let mut entity_commands = commands.entity(entity);
+            entity_commands.insert(TrackObstacleMarker);
+            if let ManifestObstacleBehaviour::Moving { amplitude, period } = *behaviour {
+                entity_commands.insert(MovingObstacle { amplitude, period });
+            }
+        }
+    }
+}
+
+/// Start the rise from the transform the manifest placed the obstacle at
+pub fn rise_obstacles(
+    mut commands: Commands,
+    q_obstacles: Query<(Entity, &Transform), (With<RiseFromGround>, Added<ManifestApplied>)>,
+) {
+    for (entity, transform) in q_obstacles.iter() {
+        commands
+            .entity(entity)
+            .remove::<RiseFromGround>()
+            .insert(AlterTransformOnce {
+                target: *transform,
+                tween: Some(TransformTween {
+                    from: TweenStart::Offset(Vec3::new(0.0, -OBSTACLE_RISE_DEPTH, 0.0)),
+                    duration: OBSTACLE_RISE_DURATION,
+                    ease: EaseFunction::QuadraticOut,
+                }),
+            });
+    }
+}
+
+/// Obstacles only start moving once they've risen, the easing would
+/// overwrite their position otherwise
+pub fn move_obstacles(
+    time: Res<Time>,
+    mut q: Query<
+        (&mut Transform, &MovingObstacle, &ManifestApplied),
+        Without<EasingComponent<Transform>>,
+    >,
+) {
+    let t = time.seconds_since_startup() as f32;
+    for (mut transform, moving, manifest) in q.iter_mut() {
+        transform.translation.x = manifest.transform.translation.x
+            + moving.amplitude * (t * std::f32::consts::TAU / moving.period).sin();
+    }
+}
+
+fn spawn_obstacle(commands: &mut Commands, asset_server: &AssetServer, obstacle: &Obstacle) {
+    let position = obstacle.position;
+    let entity = spawn_model_override(OBSTACLE_MODEL, commands, asset_server, move |manifest| {
+        manifest.transform.translation =
+            (Vec3::from(manifest.transform.translation) + position).into();
+        // Risen and moved by their transform, the collider has to follow
+        if let Some(physics) = manifest.physics.as_mut() {
+            physics.body = ManifestBodyType::KinematicPositionBased;
+        }
+    });
+
+    let mut entity_commands = commands.entity(entity);
+    // Placed right away so it isn't despawned as behind the ship before the manifest applies
+    entity_commands.insert_bundle((
+        Transform::from_translation(position),
+        GlobalTransform::from_translation(position),
+        TrackObstacleMarker,
+        RiseFromGround,
+    ));
+    if let ObstacleKind::Moving { amplitude, period } = obstacle.kind {
+        entity_commands.insert(MovingObstacle { amplitude, period });
+    }
+}
@@ -0,0 +1,175 @@
+use bevy::prelude::{debug, error};
+use serde::Deserialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Curve shipped with the game, relative to the assets folder
+pub const DIFFICULTY_CURVE_PATH: &str = "levels/difficulty.json";
+
+/// Difficulty parameters interpolated between keyframes by distance travelled
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DifficultyLevel {
+    /// Probability for every obstacle slot in a row to be filled
+    pub obstacle_density: f32,
+    /// Width of the guaranteed free gap in every row
+    pub gap_width: f32,
+    /// Fraction of spawned obstacles that move sideways
+    pub moving_obstacle_ratio: f32,
+    /// Speed level the ship can't go below
+    pub min_speed_level: usize,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct DifficultyKeyframe {
+    pub distance: f32,
+    #[serde(flatten)]
+    pub level: DifficultyLevel,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DifficultyCurve {
+    keyframes: Vec<DifficultyKeyframe>,
+}
+
+impl DifficultyCurve {
+    pub fn from_path_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match File::open(PathBuf::from("assets").join(path)) {
+            Ok(f) => match serde_json::from_reader::<_, Self>(f) {
+                Ok(mut curve) => {
+                    curve
+                        .keyframes
+                        .sort_by(|a, b| a.distance.total_cmp(&b.distance));
+                    curve
+                }
+                Err(e) => {
+                    error!(
+                        "Invalid difficulty curve `{:?}`, using the default: {}",
+                        path, e
+                    );
+                    DifficultyCurve::default()
+                }
+            },
+            Err(e) => {
+                debug!("Couldn't find difficulty curve `{:?}`: {}", path, e);
+                DifficultyCurve::default()
+            }
+        }
+    }
+
+    /// Difficulty at given distance, linearly interpolated between the
+    /// surrounding keyframes and clamped to the first and last one
+    pub fn sample(&self, distance: f32) -> DifficultyLevel {
+        let Some(first) = self.keyframes.first() else {
+            return DifficultyLevel::default();
+        };
+
+        let next = self.keyframes.partition_point(|k| k.distance <= distance);
+        if next == 0 {
+            return first.level;
+        }
+        let prev = &self.keyframes[next - 1];
+        let Some(next) = self.keyframes.get(next) else {
+            return prev.level;
+        };
+
+        let t = (distance - prev.distance) / (next.distance - prev.distance);
+        prev.level.lerp(&next.level, t)
+    }
+}
+
+impl DifficultyLevel {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            obstacle_density: lerp(self.obstacle_density, other.obstacle_density),
+            gap_width: lerp(self.gap_width, other.gap_width),
+            moving_obstacle_ratio: lerp(self.moving_obstacle_ratio, other.moving_obstacle_ratio),
+            min_speed_level: lerp(self.min_speed_level as f32, other.min_speed_level as f32)
+                as usize,
+        }
+    }
+}
+
+impl Default for DifficultyLevel {
+    fn default() -> Self {
+        Self {
+            obstacle_density: 0.1,
+            gap_width: 60.0,
+            moving_obstacle_ratio: 0.0,
+            min_speed_level: 0,
+        }
+    }
+}
+
+impl Default for DifficultyCurve {
+    fn default() -> Self {
+        Self {
+            keyframes: vec![DifficultyKeyframe {
+                distance: 0.0,
+                level: DifficultyLevel::default(),
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(
+        distance: f32,
+        obstacle_density: f32,
+        min_speed_level: usize,
+    ) -> DifficultyKeyframe {
+        DifficultyKeyframe {
+            distance,
+            level: DifficultyLevel {
+                obstacle_density,
+                gap_width: 60.0,
+                moving_obstacle_ratio: 0.0,
+                min_speed_level,
+            },
+        }
+    }
+
+    fn curve() -> DifficultyCurve {
+        DifficultyCurve {
+            keyframes: vec![
+                keyframe(100.0, 0.2, 0),
+                keyframe(200.0, 0.4, 2),
+                keyframe(400.0, 0.8, 4),
+            ],
+        }
+    }
+
+    #[test]
+    fn clamps_outside_keyframes() {
+        let curve = curve();
+        assert_eq!(curve.sample(0.0), curve.keyframes[0].level);
+        assert_eq!(curve.sample(100.0), curve.keyframes[0].level);
+        assert_eq!(curve.sample(400.0), curve.keyframes[2].level);
+        assert_eq!(curve.sample(1000.0), curve.keyframes[2].level);
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let curve = curve();
+        let level = curve.sample(150.0);
+        assert!((level.obstacle_density - 0.3).abs() < 1e-5);
+        assert_eq!(level.min_speed_level, 1);
+
+        let level = curve.sample(300.0);
+        assert!((level.obstacle_density - 0.6).abs() < 1e-5);
+        assert_eq!(level.min_speed_level, 3);
+        assert_eq!(curve.sample(200.0), curve.keyframes[1].level);
+    }
+
+    #[test]
+    fn empty_curve_samples_default() {
+        let curve = DifficultyCurve {
+            keyframes: Vec::new(),
+        };
+        assert_eq!(curve.sample(500.0), DifficultyLevel::default());
+    }
+}
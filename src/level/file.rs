@@ -0,0 +1,72 @@
+use crate::utils::manifest::{MVec3, ManifestMaterial};
+use bevy::prelude::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Hand-placed level, as saved by the editor
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct LevelFile {
+    pub objects: Vec<LevelObject>,
+}
+
+/// Single placed model. `translation` and `scale` are applied on top of the
+/// transform from the model's manifest, `material` on top of its material
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelObject {
+    pub model: String,
+    pub translation: MVec3,
+    #[serde(default = "LevelObject::default_scale")]
+    pub scale: MVec3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<ManifestMaterial>,
+}
+
+impl LevelFile {
+    /// `None` when the level hasn't been saved yet or can't be read
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        match File::open(PathBuf::from("assets").join(path)) {
+            Ok(f) => match serde_json::from_reader(f) {
+                Ok(level) => Some(level),
+                Err(e) => {
+                    error!("Invalid level file `{:?}`: {}", path, e);
+                    None
+                }
+            },
+            Err(e) => {
+                debug!("Couldn't find level file `{:?}`: {}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) {
+        let file_path = PathBuf::from("assets").join(path.as_ref());
+        if let Some(parent) = file_path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                error!("Failed to create level directory {:?}: {}", parent, err);
+                return;
+            }
+        }
+
+        match File::create(&file_path) {
+            Ok(f) => match serde_json::to_writer_pretty(f, self) {
+                Ok(()) => info!("Saved level to {:?}", file_path),
+                Err(err) => error!("Failed to write level file {:?}: {}", file_path, err),
+            },
+            Err(err) => error!("Failed to create level file {:?}: {}", file_path, err),
+        }
+    }
+}
+
+impl LevelObject {
+    fn default_scale() -> MVec3 {
+        MVec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        }
+    }
+}
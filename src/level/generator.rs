@@ -0,0 +1,122 @@
+use crate::level::difficulty::DifficultyCurve;
+use bevy::math::Vec3;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Half of the playable track width, obstacles are placed in `-TRACK_HALF_WIDTH..TRACK_HALF_WIDTH`
+pub const TRACK_HALF_WIDTH: f32 = 150.0;
+/// Distance between two obstacle rows
+pub const ROW_SPACING: f32 = 60.0;
+pub const ROWS_PER_CHUNK: usize = 16;
+/// Lateral distance between two obstacle slots in a row
+pub const SLOT_WIDTH: f32 = 10.0;
+/// Half width of the pillar used for obstacles
+pub const OBSTACLE_HALF_WIDTH: f32 = 1.0;
+/// Half depth of the pillar used for obstacles
+pub const OBSTACLE_HALF_DEPTH: f32 = 1.0;
+/// Distance before the first row so a run doesn't start in front of a wall
+pub const TRACK_START_Z: f32 = 300.0;
+
+const MOVING_AMPLITUDE: f32 = 2.0 * SLOT_WIDTH;
+const MOVING_PERIOD: std::ops::Range<f32> = 2.0..5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObstacleKind {
+    Static,
+    /// Oscillates along x around its position
+    Moving {
+        amplitude: f32,
+        period: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Obstacle {
+    pub position: Vec3,
+    pub kind: ObstacleKind,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub start_z: f32,
+    pub end_z: f32,
+    pub obstacles: Vec<Obstacle>,
+}
+
+/// Deterministic generator of obstacle chunks, the same seed always yields the same track
+pub struct TrackGenerator {
+    rng: StdRng,
+    next_z: f32,
+}
+
+impl TrackGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            next_z: TRACK_START_Z,
+        }
+    }
+
+    /// Start of the next chunk that will be generated
+    pub fn next_z(&self) -> f32 {
+        self.next_z
+    }
+
+    pub fn next_chunk(&mut self, curve: &DifficultyCurve) -> Chunk {
+        let start_z = self.next_z;
+        let mut obstacles = Vec::new();
+
+        for row in 0..ROWS_PER_CHUNK {
+            let z = start_z + row as f32 * ROW_SPACING;
+            self.generate_row(curve, z, &mut obstacles);
+        }
+
+        self.next_z = start_z + ROWS_PER_CHUNK as f32 * ROW_SPACING;
+
+        Chunk {
+            start_z,
+            end_z: self.next_z,
+            obstacles,
+        }
+    }
+
+    fn generate_row(&mut self, curve: &DifficultyCurve, z: f32, obstacles: &mut Vec<Obstacle>) {
+        let difficulty = curve.sample(z);
+
+        let half_gap = (difficulty.gap_width / 2.0).min(TRACK_HALF_WIDTH);
+        let gap_center = self
+            .rng
+            .gen_range(-TRACK_HALF_WIDTH + half_gap..=TRACK_HALF_WIDTH - half_gap);
+
+        let mut x = -TRACK_HALF_WIDTH;
+        while x <= TRACK_HALF_WIDTH {
+            let distance_to_gap = (x - gap_center).abs();
+            if distance_to_gap > half_gap
+                && self
+                    .rng
+                    .gen_bool(difficulty.obstacle_density.clamp(0.0, 1.0) as f64)
+            {
+                // Moving obstacles must never swing into the gap
+                let can_move = distance_to_gap > half_gap + MOVING_AMPLITUDE;
+                let kind = if can_move
+                    && self
+                        .rng
+                        .gen_bool(difficulty.moving_obstacle_ratio.clamp(0.0, 1.0) as f64)
+                {
+                    ObstacleKind::Moving {
+                        amplitude: MOVING_AMPLITUDE,
+                        period: self.rng.gen_range(MOVING_PERIOD),
+                    }
+                } else {
+                    ObstacleKind::Static
+                };
+
+                obstacles.push(Obstacle {
+                    position: Vec3::new(x, 2.0, z),
+                    kind,
+                });
+            }
+            x += SLOT_WIDTH;
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod difficulty;
+pub mod file;
+pub mod generator;
+pub mod navigability;
+pub mod preview;
@@ -0,0 +1,551 @@
+//! Offline analysis of generated tracks, proves that a chunk can be flown
+//! through using the same lateral handling as `fly_ship`.
+//!
+//! Chunks are checked at every speed level the ship can fly them at. The ship
+//! can speed up between chunks but never slow down, speeding up within a
+//! chunk isn't simulated. Moving obstacles block the whole width they sweep,
+//! every step of the ship is swept against the whole depth of the obstacles.
+
+use crate::entities::ship::{
+    lateral_velocity_step, terminal_velocity_z, LINEAR_DAMPING, SHIP_HALF_EXTENTS,
+    TERMINAL_VELOCITY_X, TERMINAL_VELOCITY_Z,
+};
+use crate::level::difficulty::DifficultyCurve;
+use crate::level::generator::{
+    Chunk, Obstacle, ObstacleKind, TrackGenerator, OBSTACLE_HALF_DEPTH, OBSTACLE_HALF_WIDTH,
+    ROWS_PER_CHUNK, TRACK_HALF_WIDTH,
+};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Fixed step the ship is simulated with
+pub const SIMULATION_DT: f32 = 1.0 / 60.0;
+
+/// Resolution states are merged at, finer is more precise but slower
+const POSITION_QUANTUM: f32 = 2.0;
+const VELOCITY_QUANTUM: f32 = 10.0;
+/// Quantized states either side of the track center and of flying straight
+const POSITION_CELLS: i32 = (TRACK_HALF_WIDTH / POSITION_QUANTUM) as i32;
+const VELOCITY_CELLS: i32 = (TERMINAL_VELOCITY_X / VELOCITY_QUANTUM) as i32;
+
+/// Depth around its `z` a row blocks, widened by the ship size
+const ROW_HALF_DEPTH: f32 = OBSTACLE_HALF_DEPTH + SHIP_HALF_EXTENTS;
+
+const DIRECTIONS: [f32; 3] = [-1.0, 0.0, 1.0];
+
+/// Speed levels above this one fly at the same terminal velocity
+const MAX_SPEED_LEVEL: usize = TERMINAL_VELOCITY_Z.len() - 1;
+/// Repairs tried on a chunk before giving up on it
+const MAX_REPAIRS: usize = 2 * ROWS_PER_CHUNK;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShipState {
+    pub x: f32,
+    pub velocity_x: f32,
+    pub speed_level: usize,
+}
+
+/// Point of a feasible path through a chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathPoint {
+    pub x: f32,
+    pub z: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct NavigablePath {
+    pub points: Vec<PathPoint>,
+    /// Every state the ship can be in when leaving the chunk, entry for the next one
+    pub exit_states: Vec<ShipState>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Blocked {
+    /// Row no state made it through
+    pub z: f32,
+    /// Reachable position closest to the row's widest opening
+    pub x: f32,
+}
+
+/// Chunk repairs couldn't make beatable
+#[derive(Clone, Debug, Error)]
+#[error("Chunk at {} is still blocked after {} repairs", .start_z, .repaired.len())]
+pub struct Unnavigable {
+    pub start_z: f32,
+    /// Every repair tried, the last row couldn't be cleared
+    pub repaired: Vec<Blocked>,
+}
+
+/// Quantized states already reached in a layer, one bit per state
+struct StateGrid {
+    bits: Vec<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SeedReport {
+    pub seed: u64,
+    pub chunks: usize,
+    /// Rows that had to be repaired, empty if the track was beatable as generated
+    pub blocked: Vec<Blocked>,
+    /// Start of the chunks repairs gave up on, emptied by `clear_chunk`
+    pub unrepairable: Vec<f32>,
+}
+
+/// Lateral span blocked by an obstacle, widened by the ship size
+fn blocked_span(obstacle: &Obstacle) -> (f32, f32) {
+    let reach = match obstacle.kind {
+        ObstacleKind::Static => 0.0,
+        ObstacleKind::Moving { amplitude, .. } => amplitude,
+    };
+    let half_width = OBSTACLE_HALF_WIDTH + SHIP_HALF_EXTENTS + reach;
+    (
+        obstacle.position.x - half_width,
+        obstacle.position.x + half_width,
+    )
+}
+
+/// Index of the first of `rows` the ship runs into flying straight from
+/// `from` to `to`
+fn first_hit(rows: &[(f32, Vec<Obstacle>)], from: PathPoint, to: PathPoint) -> Option<usize> {
+    let x_at = |z: f32| from.x + (to.x - from.x) * ((z - from.z) / (to.z - from.z)).clamp(0.0, 1.0);
+
+    rows.iter().position(|(row_z, row)| {
+        let min_z = from.z.max(row_z - ROW_HALF_DEPTH);
+        let max_z = to.z.min(row_z + ROW_HALF_DEPTH);
+        if min_z > max_z {
+            return false;
+        }
+        let (a, b) = (x_at(min_z), x_at(max_z));
+        let (min_x, max_x) = (a.min(b), a.max(b));
+        row.iter().any(|obstacle| {
+            let (min, max) = blocked_span(obstacle);
+            max_x > min && min_x < max
+        })
+    })
+}
+
+fn quantize(state: &ShipState) -> (i32, i32) {
+    (
+        (state.x / POSITION_QUANTUM).round() as i32,
+        (state.velocity_x / VELOCITY_QUANTUM).round() as i32,
+    )
+}
+
+impl StateGrid {
+    fn new() -> Self {
+        let states = (2 * POSITION_CELLS + 1) * (2 * VELOCITY_CELLS + 1);
+        StateGrid {
+            bits: vec![0; (states as usize + 63) / 64],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    /// Mark the quantized `state`, returns false if it was already reached
+    fn insert(&mut self, state: &ShipState) -> bool {
+        let (x, velocity_x) = quantize(state);
+        let x = x.clamp(-POSITION_CELLS, POSITION_CELLS) + POSITION_CELLS;
+        let velocity_x = velocity_x.clamp(-VELOCITY_CELLS, VELOCITY_CELLS) + VELOCITY_CELLS;
+        let index = (x * (2 * VELOCITY_CELLS + 1) + velocity_x) as usize;
+
+        let (word, bit) = (index / 64, 1 << (index % 64));
+        let reached = self.bits[word] & bit != 0;
+        self.bits[word] |= bit;
+        !reached
+    }
+}
+
+/// Obstacles grouped in rows of equal `z`, sorted front to back
+fn rows(chunk: &Chunk) -> Vec<(f32, Vec<Obstacle>)> {
+    let mut obstacles = chunk.obstacles.clone();
+    obstacles.sort_by(|a, b| a.position.z.total_cmp(&b.position.z));
+
+    let mut rows: Vec<(f32, Vec<Obstacle>)> = Vec::new();
+    for obstacle in obstacles {
+        match rows.last_mut() {
+            Some((z, row)) if *z == obstacle.position.z => row.push(obstacle),
+            _ => rows.push((obstacle.position.z, vec![obstacle])),
+        }
+    }
+    rows
+}
+
+/// Search for a way through `chunk` starting from any of `entry` states, at
+/// every speed level it can be flown at. States can enter at their own level
+/// or any higher one. The returned points are the path at the lowest level
+pub fn find_path(
+    chunk: &Chunk,
+    curve: &DifficultyCurve,
+    entry: &[ShipState],
+) -> Result<NavigablePath, Blocked> {
+    let rows = rows(chunk);
+    let lowest = entry
+        .iter()
+        .map(|state| state.speed_level)
+        .min()
+        .unwrap_or(0)
+        .max(curve.sample(chunk.start_z).min_speed_level)
+        .min(MAX_SPEED_LEVEL);
+
+    let mut points = None;
+    let mut exit_states = HashMap::new();
+    for speed_level in lowest..=MAX_SPEED_LEVEL {
+        let level_entry: Vec<ShipState> = entry
+            .iter()
+            .filter(|state| state.speed_level <= speed_level)
+            .map(|state| ShipState {
+                speed_level,
+                ..*state
+            })
+            .collect();
+
+        let path = find_path_at_level(chunk, &rows, curve, &level_entry, speed_level)?;
+        for state in path.exit_states {
+            exit_states
+                .entry((quantize(&state), state.speed_level))
+                .or_insert(state);
+        }
+        points.get_or_insert(path.points);
+    }
+
+    Ok(NavigablePath {
+        points: points.unwrap_or_default(),
+        exit_states: exit_states.into_values().collect(),
+    })
+}
+
+/// Search for a way through `chunk` flying at `speed_level`, or faster where
+/// the difficulty curve requires it
+fn find_path_at_level(
+    chunk: &Chunk,
+    rows: &[(f32, Vec<Obstacle>)],
+    curve: &DifficultyCurve,
+    entry: &[ShipState],
+    speed_level: usize,
+) -> Result<NavigablePath, Blocked> {
+    let damping = 1.0 / (1.0 + SIMULATION_DT * LINEAR_DAMPING);
+    let end_z = rows
+        .last()
+        .map_or(chunk.start_z, |(row_z, _)| row_z + ROW_HALF_DEPTH);
+
+    // Every layer keeps its `z` and reached states with the index of their parent in the
+    // previous layer
+    let mut layers: Vec<(f32, Vec<(ShipState, usize)>)> = vec![(
+        chunk.start_z,
+        entry.iter().map(|state| (*state, 0)).collect(),
+    )];
+    let mut grid = StateGrid::new();
+    let mut z = chunk.start_z;
+    // Rows before this one are behind every state
+    let mut first_row = 0;
+
+    while z < end_z {
+        let speed_level = speed_level.max(curve.sample(z).min_speed_level);
+        let next_z = z + terminal_velocity_z(speed_level) * damping * SIMULATION_DT;
+        while rows[first_row].0 + ROW_HALF_DEPTH < z {
+            first_row += 1;
+        }
+        let ahead = &rows[first_row..];
+        let overlapping = ahead
+            .iter()
+            .take_while(|(row_z, _)| row_z - ROW_HALF_DEPTH <= next_z)
+            .count();
+
+        let (_, previous) = layers.last().unwrap();
+        let mut next = Vec::new();
+        let mut hit: Option<usize> = None;
+        grid.clear();
+
+        for (parent, (state, _)) in previous.iter().enumerate() {
+            for direction in DIRECTIONS {
+                let velocity_x =
+                    lateral_velocity_step(state.velocity_x, direction, SIMULATION_DT) * damping;
+                let next_state = ShipState {
+                    x: state.x + velocity_x * SIMULATION_DT,
+                    velocity_x,
+                    speed_level: state.speed_level,
+                };
+                if next_state.x.abs() > TRACK_HALF_WIDTH {
+                    continue;
+                }
+
+                let from = PathPoint { x: state.x, z };
+                let to = PathPoint {
+                    x: next_state.x,
+                    z: next_z,
+                };
+                if let Some(row) = first_hit(&ahead[..overlapping], from, to) {
+                    hit = Some(hit.map_or(row, |hit| hit.min(row)));
+                } else if grid.insert(&next_state) {
+                    next.push((next_state, parent));
+                }
+            }
+        }
+
+        if next.is_empty() {
+            let (row_z, row) = &ahead[hit.unwrap_or_default()];
+            let opening = widest_opening(row);
+            let closest_x = previous
+                .iter()
+                .map(|(state, _)| state.x)
+                .min_by(|a, b| (a - opening).abs().total_cmp(&(b - opening).abs()));
+            return Err(Blocked {
+                z: *row_z,
+                x: closest_x.unwrap_or(opening),
+            });
+        }
+
+        z = next_z;
+        layers.push((z, next));
+    }
+
+    // The curve may have forced a higher level along the way
+    let (_, last) = layers.last().unwrap();
+    let exit_level = speed_level.max(curve.sample(z).min_speed_level);
+    let exit_states = last
+        .iter()
+        .map(|(state, _)| ShipState {
+            speed_level: exit_level,
+            ..*state
+        })
+        .collect();
+
+    Ok(NavigablePath {
+        points: backtrack(&layers),
+        exit_states,
+    })
+}
+
+/// Walk parents back from the exit state closest to the track center
+fn backtrack(layers: &[(f32, Vec<(ShipState, usize)>)]) -> Vec<PathPoint> {
+    let (_, last) = layers.last().unwrap();
+    let mut index = (0..last.len())
+        .min_by(|a, b| last[*a].0.x.abs().total_cmp(&last[*b].0.x.abs()))
+        .unwrap_or_default();
+
+    let mut points = Vec::with_capacity(layers.len());
+    for (z, layer) in layers.iter().rev() {
+        let (state, parent) = layer[index];
+        points.push(PathPoint { x: state.x, z: *z });
+        index = parent;
+    }
+    points.reverse();
+    points
+}
+
+/// Center of the widest free span in a row
+fn widest_opening(row: &[Obstacle]) -> f32 {
+    let mut spans: Vec<(f32, f32)> = row.iter().map(blocked_span).collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut best = (0.0, f32::MIN);
+    let mut free_from = -TRACK_HALF_WIDTH;
+    for (min, max) in spans
+        .into_iter()
+        .chain([(TRACK_HALF_WIDTH, TRACK_HALF_WIDTH)])
+    {
+        if min - free_from > best.1 {
+            best = ((min + free_from) / 2.0, min - free_from);
+        }
+        free_from = free_from.max(max);
+    }
+    best.0
+}
+
+/// Open a gap in the blocked row around the reachable position closest to
+/// the row's widest opening. Returns false if there was nothing to remove
+fn repair(chunk: &mut Chunk, blocked: Blocked, curve: &DifficultyCurve) -> bool {
+    let half_gap = curve.sample(blocked.z).gap_width / 2.0;
+    let count = chunk.obstacles.len();
+
+    chunk.obstacles.retain(|obstacle| {
+        let (min, max) = blocked_span(obstacle);
+        obstacle.position.z != blocked.z
+            || max <= blocked.x - half_gap
+            || min >= blocked.x + half_gap
+    });
+    chunk.obstacles.len() < count
+}
+
+/// Check `chunk` and repair it until it can be flown through. Returns the
+/// path found and every row that needed repairs, or an error once repairs
+/// stop removing obstacles or `MAX_REPAIRS` is reached
+pub fn ensure_navigable(
+    chunk: &mut Chunk,
+    curve: &DifficultyCurve,
+    entry: &[ShipState],
+) -> Result<(NavigablePath, Vec<Blocked>), Unnavigable> {
+    let mut repaired = Vec::new();
+    loop {
+        let blocked = match find_path(chunk, curve, entry) {
+            Ok(path) => return Ok((path, repaired)),
+            Err(blocked) => blocked,
+        };
+        repaired.push(blocked);
+
+        if repaired.len() > MAX_REPAIRS || !repair(chunk, blocked, curve) {
+            return Err(Unnavigable {
+                start_z: chunk.start_z,
+                repaired,
+            });
+        }
+    }
+}
+
+/// Fallback for chunks `ensure_navigable` gave up on, removes every obstacle
+/// so the ship flies straight through
+pub fn clear_chunk(chunk: &mut Chunk, entry: &[ShipState]) -> NavigablePath {
+    chunk.obstacles.clear();
+
+    let x = entry
+        .iter()
+        .map(|state| state.x)
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+        .unwrap_or_default();
+    NavigablePath {
+        points: vec![
+            PathPoint {
+                x,
+                z: chunk.start_z,
+            },
+            PathPoint { x, z: chunk.end_z },
+        ],
+        exit_states: entry.to_vec(),
+    }
+}
+
+/// Ship state at the start of a run
+pub fn start_states() -> Vec<ShipState> {
+    vec![ShipState {
+        x: 0.0,
+        velocity_x: 0.0,
+        speed_level: 0,
+    }]
+}
+
+/// Generate the track for `seed` up to `distance` and report every row that
+/// wasn't beatable as generated
+pub fn check_seed(seed: u64, distance: f32, curve: &DifficultyCurve) -> SeedReport {
+    let mut generator = TrackGenerator::new(seed);
+    let mut entry = start_states();
+    let mut report = SeedReport {
+        seed,
+        chunks: 0,
+        blocked: Vec::new(),
+        unrepairable: Vec::new(),
+    };
+
+    while generator.next_z() < distance {
+        let mut chunk = generator.next_chunk(curve);
+        let path = match ensure_navigable(&mut chunk, curve, &entry) {
+            Ok((path, blocked)) => {
+                report.blocked.extend(blocked);
+                path
+            }
+            Err(err) => {
+                report.blocked.extend(err.repaired);
+                report.unrepairable.push(err.start_z);
+                clear_chunk(&mut chunk, &entry)
+            }
+        };
+        entry = path.exit_states;
+        report.chunks += 1;
+    }
+
+    report
+}
+
+pub fn scan_seeds(
+    seeds: impl Iterator<Item = u64>,
+    distance: f32,
+    curve: &DifficultyCurve,
+) -> Vec<SeedReport> {
+    seeds
+        .map(|seed| check_seed(seed, distance, curve))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::generator::TRACK_START_Z;
+    use bevy::math::Vec3;
+
+    /// Chunk with a single row of static obstacles packed across the track,
+    /// except where `free` holds
+    fn chunk_with_row(row_z: f32, free: impl Fn(f32) -> bool) -> Chunk {
+        let mut obstacles = Vec::new();
+        let mut x = -TRACK_HALF_WIDTH;
+        while x <= TRACK_HALF_WIDTH {
+            if !free(x) {
+                obstacles.push(Obstacle {
+                    position: Vec3::new(x, 2.0, row_z),
+                    kind: ObstacleKind::Static,
+                });
+            }
+            x += 2.0;
+        }
+        Chunk {
+            start_z: 0.0,
+            end_z: 2.0 * row_z,
+            obstacles,
+        }
+    }
+
+    #[test]
+    fn passes_through_gap() {
+        let chunk = chunk_with_row(100.0, |x| x.abs() < 30.0);
+        let curve = DifficultyCurve::default();
+
+        let path = find_path(&chunk, &curve, &start_states()).unwrap();
+        assert!(!path.exit_states.is_empty());
+        assert_eq!(path.points.first().unwrap().z, 0.0);
+        assert!(path.points.last().unwrap().z >= 100.0 + ROW_HALF_DEPTH);
+    }
+
+    #[test]
+    fn blocked_by_wall() {
+        let chunk = chunk_with_row(100.0, |_| false);
+        let curve = DifficultyCurve::default();
+
+        let blocked = find_path(&chunk, &curve, &start_states()).unwrap_err();
+        assert_eq!(blocked.z, 100.0);
+    }
+
+    #[test]
+    fn repairs_wall() {
+        let mut chunk = chunk_with_row(100.0, |_| false);
+        let count = chunk.obstacles.len();
+        let curve = DifficultyCurve::default();
+
+        let (_, repaired) = ensure_navigable(&mut chunk, &curve, &start_states()).unwrap();
+        assert!(!repaired.is_empty());
+        assert!(chunk.obstacles.len() < count);
+        assert!(find_path(&chunk, &curve, &start_states()).is_ok());
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let curve = DifficultyCurve::default();
+        let mut first = TrackGenerator::new(42);
+        let mut second = TrackGenerator::new(42);
+        for _ in 0..3 {
+            let (a, b) = (first.next_chunk(&curve), second.next_chunk(&curve));
+            assert_eq!(a.start_z, b.start_z);
+            assert_eq!(a.obstacles.len(), b.obstacles.len());
+            for (a, b) in a.obstacles.iter().zip(&b.obstacles) {
+                assert_eq!(a.position, b.position);
+                assert_eq!(a.kind, b.kind);
+            }
+        }
+
+        let distance = TRACK_START_Z + 1.0;
+        let first = check_seed(42, distance, &curve);
+        let second = check_seed(42, distance, &curve);
+        assert_eq!(first.chunks, 1);
+        assert_eq!(first.blocked, second.blocked);
+        assert_eq!(first.unrepairable, second.unrepairable);
+    }
+}
@@ -0,0 +1,155 @@
+//! Top-down maps of generated tracks, for reviewing seeds without running the game
+
+use crate::level::difficulty::DifficultyCurve;
+use crate::level::generator::{
+    Chunk, ObstacleKind, TrackGenerator, OBSTACLE_HALF_WIDTH, ROW_SPACING, SLOT_WIDTH,
+    TRACK_HALF_WIDTH,
+};
+use crate::level::navigability::{clear_chunk, ensure_navigable, start_states, PathPoint};
+use image::{Rgb, RgbImage};
+
+/// Pixels per unit across the track
+const IMAGE_SCALE_X: f32 = 2.0;
+/// Pixels per unit along the track
+const IMAGE_SCALE_Z: f32 = 0.2;
+
+const COLOR_GROUND: Rgb<u8> = Rgb([24, 24, 32]);
+const COLOR_STATIC: Rgb<u8> = Rgb([200, 200, 200]);
+const COLOR_MOVING: Rgb<u8> = Rgb([240, 140, 40]);
+const COLOR_PATH: Rgb<u8> = Rgb([230, 40, 40]);
+
+/// Everything generated for a seed between two distances
+pub struct TrackPreview {
+    pub from: f32,
+    pub to: f32,
+    pub chunks: Vec<Chunk>,
+    pub path: Vec<PathPoint>,
+}
+
+impl TrackPreview {
+    /// Run the generator and navigability checker like the game does, keeping
+    /// chunks overlapping `from..to`
+    pub fn generate(seed: u64, from: f32, to: f32, curve: &DifficultyCurve) -> Self {
+        let mut generator = TrackGenerator::new(seed);
+        let mut entry = start_states();
+        let mut chunks = Vec::new();
+        let mut path = Vec::new();
+
+        while generator.next_z() < to {
+            let mut chunk = generator.next_chunk(curve);
+            let chunk_path = match ensure_navigable(&mut chunk, curve, &entry) {
+                Ok((path, _)) => path,
+                Err(_) => clear_chunk(&mut chunk, &entry),
+            };
+            entry = chunk_path.exit_states;
+
+            if chunk.end_z > from {
+                path.extend(
+                    chunk_path
+                        .points
+                        .into_iter()
+                        .filter(|point| point.z >= from && point.z <= to),
+                );
+                chunks.push(chunk);
+            }
+        }
+
+        Self {
+            from,
+            to,
+            chunks,
+            path,
+        }
+    }
+
+    /// One line per row, farthest row on top. `#` static obstacle, `~` moving
+    /// obstacle, `*` feasible path
+    pub fn to_ascii(&self) -> String {
+        let columns = (2.0 * TRACK_HALF_WIDTH / SLOT_WIDTH) as usize + 1;
+        let rows = ((self.to - self.from) / (ROW_SPACING / 2.0))
+            .ceil()
+            .max(0.0) as usize;
+        let mut grid = vec![vec!['.'; columns]; rows];
+
+        let mut put = |x: f32, z: f32, c: char| {
+            if z < self.from || z >= self.to {
+                return;
+            }
+            let column = ((x + TRACK_HALF_WIDTH) / SLOT_WIDTH).round();
+            let row = ((z - self.from) / (ROW_SPACING / 2.0)) as usize;
+            if column >= 0.0 && (column as usize) < columns && row < rows {
+                grid[row][column as usize] = c;
+            }
+        };
+
+        for point in self.path.iter() {
+            put(point.x, point.z, '*');
+        }
+        for chunk in self.chunks.iter() {
+            for obstacle in chunk.obstacles.iter() {
+                let c = match obstacle.kind {
+                    ObstacleKind::Static => '#',
+                    ObstacleKind::Moving { .. } => '~',
+                };
+                put(obstacle.position.x, obstacle.position.z, c);
+            }
+        }
+
+        grid.iter()
+            .rev()
+            .map(|row| row.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Top-down image, far end of the range on top
+    pub fn to_image(&self) -> RgbImage {
+        let width = (2.0 * TRACK_HALF_WIDTH * IMAGE_SCALE_X).ceil() as u32;
+        let height = ((self.to - self.from) * IMAGE_SCALE_Z).ceil().max(1.0) as u32;
+        let mut image = RgbImage::from_pixel(width, height, COLOR_GROUND);
+
+        let to_pixel = |x: f32, z: f32| {
+            (
+                (x + TRACK_HALF_WIDTH) * IMAGE_SCALE_X,
+                height as f32 - (z - self.from) * IMAGE_SCALE_Z,
+            )
+        };
+        let mut fill = |min: (f32, f32), max: (f32, f32), color: Rgb<u8>| {
+            let x0 = min.0.max(0.0) as u32;
+            let x1 = (max.0.ceil() as u32).min(width);
+            let y0 = max.1.max(0.0) as u32;
+            let y1 = (min.1.ceil() as u32).min(height);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    image.put_pixel(x, y, color);
+                }
+            }
+        };
+
+        for chunk in self.chunks.iter() {
+            for obstacle in chunk.obstacles.iter() {
+                let (reach, color) = match obstacle.kind {
+                    ObstacleKind::Static => (0.0, COLOR_STATIC),
+                    ObstacleKind::Moving { amplitude, .. } => (amplitude, COLOR_MOVING),
+                };
+                let half_width = OBSTACLE_HALF_WIDTH + reach;
+                let position = obstacle.position;
+                fill(
+                    to_pixel(position.x - half_width, position.z - OBSTACLE_HALF_WIDTH),
+                    to_pixel(position.x + half_width, position.z + OBSTACLE_HALF_WIDTH),
+                    color,
+                );
+            }
+        }
+
+        for point in self.path.iter() {
+            fill(
+                to_pixel(point.x - 0.5, point.z),
+                to_pixel(point.x + 0.5, point.z + 1.0 / IMAGE_SCALE_Z),
+                COLOR_PATH,
+            );
+        }
+
+        image
+    }
+}
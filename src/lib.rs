@@ -0,0 +1,14 @@
+#![feature(let_else)]
+#![feature(path_try_exists)]
+#![feature(generic_const_exprs)]
+
+use bevy::prelude::Component;
+
+pub mod editor;
+pub mod entities;
+pub mod level;
+pub mod materials;
+pub mod utils;
+
+#[derive(Component)]
+pub struct MainCameraMarker;
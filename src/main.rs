@@ -1,33 +1,54 @@
-#![feature(let_else)]
-#![feature(path_try_exists)]
-#![feature(generic_const_exprs)]
-
-use crate::entities::environment::spawn_sample_scene;
-use crate::entities::ship::ShipControlPlugin;
-use crate::materials::skybox::SkyboxPlugin;
-use crate::utils::alter_transform_once::AlterTransformOncePlugin;
-use crate::utils::local_settings::LocalSettingsPlugin;
+use bevy::asset::AssetServerSettings;
 use bevy::prelude::*;
 use bevy::DefaultPlugins;
+use race_the_moon::editor::EditorPlugin;
+use race_the_moon::entities::camera::CameraPlugin;
+use race_the_moon::entities::environment::spawn_sample_scene;
+use race_the_moon::entities::ship::ShipControlPlugin;
+use race_the_moon::entities::track::TrackPlugin;
+use race_the_moon::level::difficulty::{DifficultyCurve, DIFFICULTY_CURVE_PATH};
+use race_the_moon::level::navigability::scan_seeds;
+use race_the_moon::materials::day_night::DayNightPlugin;
+use race_the_moon::materials::fog::FogPlugin;
+use race_the_moon::materials::instancing::InstancingPlugin;
+use race_the_moon::materials::post_processing::PostProcessingPlugin;
+use race_the_moon::materials::skybox::SkyboxPlugin;
+use race_the_moon::utils::alter_transform_once::AlterTransformOncePlugin;
+use race_the_moon::utils::local_settings::LocalSettingsPlugin;
+use race_the_moon::utils::spawn::SpawnPlugin;
 
-use crate::entities::camera::CameraPlugin;
 use bevy_easings::EasingsPlugin;
 use bevy_rapier3d::prelude::*;
 
-mod entities;
-mod materials;
-mod utils;
-
-#[derive(Component)]
-pub struct MainCameraMarker;
-
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--check-seeds") {
+        let count = args
+            .get(i + 1)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(100);
+        let distance = args
+            .iter()
+            .position(|arg| arg == "--distance")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(20000.0);
+        check_seeds(count, distance);
+        return;
+    }
+
     let mut app = App::new();
 
+    // Until the skybox is converted and its irradiance takes over
     app.insert_resource(AmbientLight {
         color: Color::WHITE,
         brightness: 0.5,
     })
+    // Manifests and shaders reload when edited during development
+    .insert_resource(AssetServerSettings {
+        watch_for_changes: cfg!(debug_assertions),
+        ..Default::default()
+    })
     .add_plugins(DefaultPlugins)
     .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
     .add_plugin(RapierRenderPlugin)
@@ -35,13 +56,65 @@ fn main() {
     .add_plugin(LocalSettingsPlugin {
         filename: "settings.json".to_string(),
     })
-    .add_plugin(SkyboxPlugin)
+    // Before every plugin drawing with the mesh view bind group it adds the fog to
+    .add_plugin(FogPlugin)
+    .add_plugin(SkyboxPlugin::default())
+    .add_plugin(DayNightPlugin::default())
+    .add_plugin(InstancingPlugin)
     .add_plugin(ShipControlPlugin)
+    .add_plugin(TrackPlugin {
+        seed: rand::random(),
+        difficulty_curve: DIFFICULTY_CURVE_PATH.to_string(),
+    })
     .add_plugin(CameraPlugin)
     .add_plugin(AlterTransformOncePlugin)
-    // .add_plugin(VignetteShaderPlugin)
+    .add_plugin(SpawnPlugin)
+    .add_plugin(EditorPlugin {
+        level_path: "levels/custom.json".to_string(),
+    })
+    .add_plugin(PostProcessingPlugin)
     .add_startup_system(spawn_sample_scene);
 
     // bevy_mod_debugdump::print_render_graph(&mut app);
     app.run();
 }
+
+/// Run the navigability checker over seeds `0..count` and exit with an error
+/// if any of them needed repairs
+fn check_seeds(count: u64, distance: f32) {
+    let curve = DifficultyCurve::from_path_or_default(DIFFICULTY_CURVE_PATH);
+    let reports = scan_seeds(0..count, distance, &curve);
+
+    let mut unbeatable = 0;
+    for report in reports.iter() {
+        if report.blocked.is_empty() {
+            continue;
+        }
+        unbeatable += 1;
+        println!(
+            "seed {}: {} blocked rows in {} chunks",
+            report.seed,
+            report.blocked.len(),
+            report.chunks
+        );
+        for blocked in report.blocked.iter() {
+            println!(
+                "    z = {:.0}, closest reachable x = {:.1}",
+                blocked.z, blocked.x
+            );
+        }
+        for start_z in report.unrepairable.iter() {
+            println!("    chunk at z = {:.0} couldn't be repaired", start_z);
+        }
+    }
+
+    println!(
+        "{} of {} seeds beatable up to {}",
+        reports.len() - unbeatable,
+        reports.len(),
+        distance
+    );
+    if unbeatable > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,575 @@
+//! CPU conversions of sky textures into the 6-layer array `skybox.wgsl`
+//! samples. Layers are ordered +X, -X, +Y, -Y, +Z, -Z
+
+use bevy::prelude::Image;
+use bevy::render::render_resource::{Extent3d, TextureFormat};
+use bevy::render::texture::TextureFormatPixelInfo;
+use half::f16;
+use std::f32::consts::PI;
+
+/// How the 6 faces are arranged in the source texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubemapLayout {
+    /// Faces stacked top to bottom in layer order
+    VerticalStrip,
+    /// Faces side by side left to right in layer order
+    HorizontalStrip,
+    /// 4x3 cross, `+Y` above and `-Y` below `+Z`:
+    /// ```text
+    ///     +Y
+    /// -X  +Z  +X  -Z
+    ///     -Y
+    /// ```
+    HorizontalCross,
+    /// 3x4 cross, `-Z` hangs upside down below `-Y`:
+    /// ```text
+    ///     +Y
+    /// -X  +Z  +X
+    ///     -Y
+    ///     -Z
+    /// ```
+    VerticalCross,
+    /// 3x2 grid:
+    /// ```text
+    /// +X  -X  +Y
+    /// -Y  +Z  -Z
+    /// ```
+    Grid3x2,
+    /// Equirectangular panorama, e.g. an `.hdr` or `.exr` file, projected on
+    /// faces of `face_size` pixels. Float textures are kept as `Rgba16Float`
+    /// so the HDR range survives and the skybox sampler can still filter them
+    Equirectangular { face_size: u32 },
+}
+
+/// Source texture split in face-sized cells
+struct FaceGrid {
+    columns: u32,
+    rows: u32,
+    /// Cell of every face in layer order
+    faces: [FaceCell; 6],
+}
+
+/// Where a face lives in the source texture, in face-sized cells
+#[derive(Clone, Copy)]
+struct FaceCell {
+    column: u32,
+    row: u32,
+    rotated: bool,
+}
+
+const fn cell(column: u32, row: u32) -> FaceCell {
+    FaceCell {
+        column,
+        row,
+        rotated: false,
+    }
+}
+
+impl CubemapLayout {
+    /// How the faces are laid out, `None` for panoramas which are projected
+    /// instead
+    fn grid(&self) -> Option<FaceGrid> {
+        let (columns, rows, faces) = match self {
+            CubemapLayout::VerticalStrip => (
+                1,
+                6,
+                [
+                    cell(0, 0),
+                    cell(0, 1),
+                    cell(0, 2),
+                    cell(0, 3),
+                    cell(0, 4),
+                    cell(0, 5),
+                ],
+            ),
+            CubemapLayout::HorizontalStrip => (
+                6,
+                1,
+                [
+                    cell(0, 0),
+                    cell(1, 0),
+                    cell(2, 0),
+                    cell(3, 0),
+                    cell(4, 0),
+                    cell(5, 0),
+                ],
+            ),
+            CubemapLayout::HorizontalCross => (
+                4,
+                3,
+                [
+                    cell(2, 1),
+                    cell(0, 1),
+                    cell(1, 0),
+                    cell(1, 2),
+                    cell(1, 1),
+                    cell(3, 1),
+                ],
+            ),
+            CubemapLayout::VerticalCross => (
+                3,
+                4,
+                [
+                    cell(2, 1),
+                    cell(0, 1),
+                    cell(1, 0),
+                    cell(1, 2),
+                    cell(1, 1),
+                    FaceCell {
+                        rotated: true,
+                        ..cell(1, 3)
+                    },
+                ],
+            ),
+            CubemapLayout::Grid3x2 => (
+                3,
+                2,
+                [
+                    cell(0, 0),
+                    cell(1, 0),
+                    cell(2, 0),
+                    cell(0, 1),
+                    cell(1, 1),
+                    cell(2, 1),
+                ],
+            ),
+            CubemapLayout::Equirectangular { .. } => return None,
+        };
+        Some(FaceGrid {
+            columns,
+            rows,
+            faces,
+        })
+    }
+
+    /// Re-pack `image` into a vertical strip and reinterpret it as a 6-layer
+    /// array. Fails if the image size doesn't match the layout or the image
+    /// already is an array, leaving it untouched
+    pub fn convert(&self, image: &mut Image) -> Result<(), String> {
+        let size = image.texture_descriptor.size;
+        if size.depth_or_array_layers != 1 {
+            return Err(format!(
+                "texture already has {} layers",
+                size.depth_or_array_layers
+            ));
+        }
+        if size.width == 0 || size.height == 0 {
+            return Err("texture is empty".to_string());
+        }
+        let pixel_size = pixel_size(image.texture_descriptor.format)?;
+        let expected_len = (size.width * size.height) as usize * pixel_size;
+        if image.data.len() != expected_len {
+            return Err(format!(
+                "texture holds {} bytes instead of {} for its size",
+                image.data.len(),
+                expected_len
+            ));
+        }
+
+        let Some(grid) = self.grid() else {
+            return match *self {
+                CubemapLayout::Equirectangular { face_size } => {
+                    project_equirectangular(image, face_size)
+                }
+                _ => Err(format!("{:?} has no faces to split", self)),
+            };
+        };
+
+        let FaceGrid {
+            columns,
+            rows,
+            faces,
+        } = grid;
+        if size.width % columns != 0 || size.height % rows != 0 {
+            return Err(format!(
+                "{}x{} texture can't be split into {}x{} faces",
+                size.width, size.height, columns, rows
+            ));
+        }
+        let face_size = size.width / columns;
+        if face_size != size.height / rows {
+            return Err(format!(
+                "faces of a {}x{} texture in a {}x{} layout aren't square",
+                size.width, size.height, columns, rows
+            ));
+        }
+
+        if *self != CubemapLayout::VerticalStrip {
+            let row_size = face_size as usize * pixel_size;
+            let source_stride = size.width as usize * pixel_size;
+
+            let mut data = Vec::with_capacity(image.data.len() / (columns * rows) as usize * 6);
+            for face in faces {
+                let face_x = (face.column * face_size) as usize * pixel_size;
+                let face_y = (face.row * face_size) as usize;
+                for y in 0..face_size as usize {
+                    let source_y = if face.rotated {
+                        face_y + face_size as usize - 1 - y
+                    } else {
+                        face_y + y
+                    };
+                    let start = source_y * source_stride + face_x;
+                    let row = &image.data[start..start + row_size];
+
+                    if face.rotated {
+                        data.extend(row.chunks_exact(pixel_size).rev().flatten());
+                    } else {
+                        data.extend_from_slice(row);
+                    }
+                }
+            }
+
+            image.data = data;
+            image.texture_descriptor.size = Extent3d {
+                width: face_size,
+                height: face_size * 6,
+                depth_or_array_layers: 1,
+            };
+        }
+
+        image.reinterpret_stacked_2d_as_array(6);
+        Ok(())
+    }
+}
+
+/// Whether `image` already is the 6-layer array `convert` produces
+pub fn is_cubemap(image: &Image) -> bool {
+    image.texture_descriptor.size.depth_or_array_layers == 6
+}
+
+/// Direction through pixel `(u, v)` in `0..1` of `face`, the inverse of
+/// `face()` in `skybox.wgsl`
+pub(crate) fn face_direction(face: usize, u: f32, v: f32) -> [f32; 3] {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    match face {
+        0 => [1.0, -b, a],
+        1 => [-1.0, -b, -a],
+        2 => [a, 1.0, -b],
+        3 => [a, -1.0, b],
+        4 => [-a, -b, 1.0],
+        _ => [a, -b, -1.0],
+    }
+}
+
+/// Bytes per pixel, block compressed formats have no pixels of their own
+fn pixel_size(format: TextureFormat) -> Result<usize, String> {
+    if format.describe().block_dimensions != (1, 1) {
+        return Err(format!(
+            "compressed format {:?} can't be read per pixel",
+            format
+        ));
+    }
+    Ok(format.pixel_size())
+}
+
+/// Pixel in the texture's own encoding, rows of array layers follow each other
+pub(crate) fn read_pixel(image: &Image, x: u32, y: u32) -> Result<[f32; 4], String> {
+    let format = image.texture_descriptor.format;
+    let pixel_size = pixel_size(format)?;
+    let offset = (y * image.texture_descriptor.size.width + x) as usize * pixel_size;
+    let data = &image.data[offset..offset + pixel_size];
+
+    let mut pixel = [0.0; 4];
+    match format {
+        TextureFormat::Rgba32Float => {
+            for (channel, bytes) in pixel.iter_mut().zip(data.chunks_exact(4)) {
+                *channel = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+        }
+        TextureFormat::Rgba16Float => {
+            for (channel, bytes) in pixel.iter_mut().zip(data.chunks_exact(2)) {
+                *channel = f16::from_le_bytes([bytes[0], bytes[1]]).to_f32();
+            }
+        }
+        _ => {
+            for (channel, byte) in pixel.iter_mut().zip(data) {
+                *channel = *byte as f32 / 255.0;
+            }
+        }
+    }
+    Ok(pixel)
+}
+
+/// Bilinear sample, wrapping around horizontally
+fn sample_equirectangular(image: &Image, direction: [f32; 3]) -> Result<[f32; 4], String> {
+    let [x, y, z] = direction;
+    let length = (x * x + y * y + z * z).sqrt();
+    let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+    let v = (y / length).clamp(-1.0, 1.0).acos() / PI;
+
+    let width = image.texture_descriptor.size.width;
+    let height = image.texture_descriptor.size.height;
+    let px = u * width as f32 - 0.5;
+    let py = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+
+    let x0 = px.floor();
+    let y0 = py.floor();
+    let (tx, ty) = (px - x0, py - y0);
+    let column = |x: f32| (x as i64).rem_euclid(width as i64) as u32;
+    let (x0, x1) = (column(x0), column(x0 + 1.0));
+    let (y0, y1) = (y0 as u32, (y0 as u32 + 1).min(height - 1));
+
+    let p00 = read_pixel(image, x0, y0)?;
+    let p10 = read_pixel(image, x1, y0)?;
+    let p01 = read_pixel(image, x0, y1)?;
+    let p11 = read_pixel(image, x1, y1)?;
+
+    let mut pixel = [0.0; 4];
+    for i in 0..4 {
+        let top = p00[i] + (p10[i] - p00[i]) * tx;
+        let bottom = p01[i] + (p11[i] - p01[i]) * tx;
+        pixel[i] = top + (bottom - top) * ty;
+    }
+    Ok(pixel)
+}
+
+fn project_equirectangular(image: &mut Image, face_size: u32) -> Result<(), String> {
+    let format = image.texture_descriptor.format;
+    let is_float = match format {
+        TextureFormat::Rgba32Float => true,
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        _ => return Err(format!("unsupported panorama format {:?}", format)),
+    };
+    if face_size == 0 {
+        return Err("face size is zero".to_string());
+    }
+
+    let pixel_size = if is_float { 8 } else { 4 };
+    let mut data = Vec::with_capacity((face_size * face_size * 6) as usize * pixel_size);
+    for face in 0..6 {
+        for y in 0..face_size {
+            for x in 0..face_size {
+                let u = (x as f32 + 0.5) / face_size as f32;
+                let v = (y as f32 + 0.5) / face_size as f32;
+                let pixel = sample_equirectangular(image, face_direction(face, u, v))?;
+
+                if is_float {
+                    for channel in pixel {
+                        data.extend_from_slice(&f16::from_f32(channel).to_le_bytes());
+                    }
+                } else {
+                    data.extend(pixel.map(|channel| (channel * 255.0).round() as u8));
+                }
+            }
+        }
+    }
+
+    image.data = data;
+    if is_float {
+        image.texture_descriptor.format = TextureFormat::Rgba16Float;
+    }
+    image.texture_descriptor.size = Extent3d {
+        width: face_size,
+        height: face_size * 6,
+        depth_or_array_layers: 1,
+    };
+    image.reinterpret_stacked_2d_as_array(6);
+    Ok(())
+}
+
+impl Default for CubemapLayout {
+    fn default() -> Self {
+        CubemapLayout::VerticalStrip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::TextureDimension;
+
+    /// `width`x`height` texture whose pixels hold their own coordinates
+    fn coordinates_image(width: u32, height: u32) -> Image {
+        let data = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8, 0, 255]))
+            .collect();
+        Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8Unorm,
+        )
+    }
+
+    /// Source coordinates held by pixel `(x, y)` of `face` once converted
+    fn source_of(image: &Image, face: u32, x: u32, y: u32) -> (u32, u32) {
+        let size = image.texture_descriptor.size.width;
+        let [source_x, source_y, _, _] = read_pixel(image, x, face * size + y).unwrap();
+        (
+            (source_x * 255.0).round() as u32,
+            (source_y * 255.0).round() as u32,
+        )
+    }
+
+    /// Port of `face()` in `skybox.wgsl`, layer and `uv` a ray samples
+    fn shader_face(ray: [f32; 3]) -> (usize, f32, f32) {
+        let [x, y, z] = ray;
+        let (face, max_adj, uv) = if z.abs() >= x.abs() && z.abs() >= y.abs() {
+            let face = if z < 0.0 { 5 } else { 4 };
+            (face, 0.5 / z.abs(), (x * -z.signum(), -y))
+        } else if y.abs() >= x.abs() {
+            let face = if y < 0.0 { 3 } else { 2 };
+            (face, 0.5 / y, (x * y.signum(), -z))
+        } else {
+            let face = if x < 0.0 { 1 } else { 0 };
+            (face, 0.5 / x, (z, y * -x.signum()))
+        };
+        (face, uv.0 * max_adj + 0.5, uv.1 * max_adj + 0.5)
+    }
+
+    #[test]
+    fn face_direction_matches_shader() {
+        let steps = 8;
+        for face in 0..6 {
+            for y in 0..steps {
+                for x in 0..steps {
+                    let u = (x as f32 + 0.5) / steps as f32;
+                    let v = (y as f32 + 0.5) / steps as f32;
+                    let (sampled, sampled_u, sampled_v) = shader_face(face_direction(face, u, v));
+                    assert_eq!(sampled, face);
+                    assert!((sampled_u - u).abs() < 1e-5, "face {} u {}", face, u);
+                    assert!((sampled_v - v).abs() < 1e-5, "face {} v {}", face, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn face_direction_points_at_face() {
+        let centers = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for (face, center) in centers.into_iter().enumerate() {
+            assert_eq!(face_direction(face, 0.5, 0.5), center);
+        }
+    }
+
+    #[test]
+    fn convert_horizontal_strip() {
+        let mut image = coordinates_image(12, 2);
+        CubemapLayout::HorizontalStrip.convert(&mut image).unwrap();
+
+        assert!(is_cubemap(&image));
+        assert_eq!(image.texture_descriptor.size.width, 2);
+        assert_eq!(image.texture_descriptor.size.height, 2);
+        for face in 0..6 {
+            for y in 0..2 {
+                for x in 0..2 {
+                    assert_eq!(source_of(&image, face, x, y), (face * 2 + x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn convert_horizontal_cross() {
+        let cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+        let mut image = coordinates_image(8, 6);
+        CubemapLayout::HorizontalCross.convert(&mut image).unwrap();
+
+        for (face, (column, row)) in cells.into_iter().enumerate() {
+            for y in 0..2 {
+                for x in 0..2 {
+                    assert_eq!(
+                        source_of(&image, face as u32, x, y),
+                        (column * 2 + x, row * 2 + y),
+                        "face {}",
+                        face
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn convert_vertical_cross_rotates_back_face() {
+        let mut image = coordinates_image(6, 8);
+        CubemapLayout::VerticalCross.convert(&mut image).unwrap();
+
+        for y in 0..2 {
+            for x in 0..2 {
+                // +Z sits upright in the middle of the cross
+                assert_eq!(source_of(&image, 4, x, y), (2 + x, 2 + y));
+                // -Z hangs upside down below -Y
+                assert_eq!(source_of(&image, 5, x, y), (2 + 1 - x, 6 + 1 - y));
+            }
+        }
+    }
+
+    #[test]
+    fn convert_rejects_mismatched_size() {
+        let mut image = coordinates_image(8, 8);
+        assert!(CubemapLayout::HorizontalCross.convert(&mut image).is_err());
+        assert_eq!(image.texture_descriptor.size.width, 8);
+        assert_eq!(image.texture_descriptor.size.height, 8);
+        assert_eq!(image.texture_descriptor.size.depth_or_array_layers, 1);
+
+        let mut image = coordinates_image(7, 2);
+        assert!(CubemapLayout::HorizontalStrip.convert(&mut image).is_err());
+    }
+
+    #[test]
+    fn convert_rejects_arrays() {
+        let mut image = coordinates_image(2, 12);
+        CubemapLayout::VerticalStrip.convert(&mut image).unwrap();
+        assert!(CubemapLayout::VerticalStrip.convert(&mut image).is_err());
+        assert!(is_cubemap(&image));
+    }
+
+    /// Float panorama with the red of every column taken from `columns`
+    fn float_panorama(columns: &[f32], height: u32) -> Image {
+        let data = (0..height)
+            .flat_map(|_| columns.iter())
+            .flat_map(|&value| [value, 0.0, 0.0, 1.0])
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        Image::new(
+            Extent3d {
+                width: columns.len() as u32,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba32Float,
+        )
+    }
+
+    #[test]
+    fn equirectangular_wraps_around_seam() {
+        let image = float_panorama(&[1.0, 0.0, 0.0, 3.0], 2);
+
+        // +Z is where the panorama's left and right edges meet
+        for direction in [[0.0, 0.0, 1.0], [-0.0, 0.0, 1.0]] {
+            let [red, _, _, alpha] = sample_equirectangular(&image, direction).unwrap();
+            assert!((red - 2.0).abs() < 1e-5, "{:?} sampled {}", direction, red);
+            assert!((alpha - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn convert_equirectangular_keeps_hdr_range() {
+        let mut image = float_panorama(&[4.0; 8], 4);
+        CubemapLayout::Equirectangular { face_size: 4 }
+            .convert(&mut image)
+            .unwrap();
+
+        assert!(is_cubemap(&image));
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Rgba16Float);
+        assert_eq!(image.texture_descriptor.size.width, 4);
+        for face in 0..6 {
+            let [red, ..] = read_pixel(&image, 1, face * 4 + 2).unwrap();
+            assert!((red - 4.0).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,530 @@
+//! Component materials drawn on meshes with their own shader. A material
+//! declares its bind group layouts, shader and how to build its bind group,
+//! `CustomMaterialPlugin` takes care of pipelines, extraction, uniforms and
+//! queueing. Bind group 0 is the mesh view with the fog of `FogPlugin`, 1 the
+//! mesh and 2 the material
+
+use crate::materials::fog::FogViewLayout;
+use bevy::core_pipeline::{Opaque3d, Transparent3d};
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::ecs::system::{StaticSystemParam, SystemParam, SystemParamItem};
+use bevy::pbr::{
+    DrawMesh, MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup,
+};
+use bevy::prelude::{
+    error, App, AssetServer, Commands, Component, ComputedVisibility, Entity, FromWorld, Handle,
+    Mesh, Msaa, Plugin, Query, Res, ResMut, Shader, With, World,
+};
+use bevy::render::mesh::MeshVertexBufferLayout;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
+    SetItemPipeline, TrackedRenderPass,
+};
+use bevy::render::render_resource::std140::AsStd140;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupLayout, BindingResource, Buffer, BufferId, RenderPipelineCache,
+    RenderPipelineDescriptor, Sampler, SamplerId, SpecializedMeshPipeline,
+    SpecializedMeshPipelineError, SpecializedMeshPipelines, TextureView, TextureViewId, UniformVec,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::{ExtractedView, VisibleEntities};
+use bevy::render::{RenderApp, RenderStage};
+use bevy::utils::{HashMap, HashSet};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Where the material component lives
+pub enum MaterialBinding {
+    /// On the drawn mesh entity
+    Entity,
+    /// On the camera, shared by every `ViewMaterialMesh<M>` drawn in that
+    /// view, like the skybox cube
+    View,
+}
+
+pub trait CustomMaterial: Component + Clone {
+    /// Material values the pipeline is specialized on, `()` if none
+    type Key: Clone + Copy + Hash + PartialEq + Eq + Send + Sync + 'static;
+    /// Render world data needed to build the bind group
+    type Param: SystemParam + 'static;
+    /// Uniform buffers written for each entity, freed once the entity no
+    /// longer has the material
+    type Uniforms: MaterialUniforms;
+    /// Component extracted along with the material when its entity has one,
+    /// `NoCompanion` if there is none
+    type Companion: Component + Clone;
+
+    /// Asset path of the shader holding both the vertex and fragment stages
+    const SHADER: &'static str;
+    const BINDING: MaterialBinding = MaterialBinding::Entity;
+    /// Queue in `Transparent3d` with blending instead of `Opaque3d`
+    const TRANSPARENT: bool = false;
+
+    /// Every layout the material can be bound with, see `layout_index`
+    fn bind_group_layouts(render_device: &RenderDevice) -> Vec<BindGroupLayout>;
+
+    fn key(&self) -> Self::Key;
+
+    /// Layout used by pipelines specialized on `key`
+    fn layout_index(_key: Self::Key) -> usize {
+        0
+    }
+
+    /// Adjust the mesh pipeline, e.g. depth state or shader defs
+    fn specialize(_key: Self::Key, _descriptor: &mut RenderPipelineDescriptor) {}
+
+    /// Sort distance in the phase, from the distance of the mesh to the view
+    fn sort_distance(distance: f32) -> f32 {
+        distance
+    }
+
+    /// Write the uniforms of `entity` and list what its bind group binds,
+    /// `None` until those resources are ready
+    fn prepare(
+        &self,
+        entity: Entity,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        uniforms: &mut Self::Uniforms,
+        param: &mut SystemParamItem<Self::Param>,
+    ) -> Option<Vec<BoundResource>>;
+
+    /// Only called when `prepare` lists other resources than last time
+    fn bind_group(
+        &self,
+        entity: Entity,
+        render_device: &RenderDevice,
+        pipeline: &CustomPipeline<Self>,
+        uniforms: &Self::Uniforms,
+        param: &mut SystemParamItem<Self::Param>,
+    ) -> Option<BindGroup>;
+}
+
+/// Uniform buffers of a material, see `CustomMaterial::Uniforms`
+pub trait MaterialUniforms: Default + Send + Sync + 'static {
+    /// Free the buffers of entities `keep` returns false for
+    fn retain(&mut self, keep: &dyn Fn(Entity) -> bool);
+}
+
+/// Identity of a resource bound by a material
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BoundResource {
+    Buffer(BufferId),
+    TextureView(TextureViewId),
+    Sampler(SamplerId),
+}
+
+/// `CustomMaterial::Companion` of materials that only need themselves
+#[derive(Component, Clone)]
+pub struct NoCompanion;
+
+/// Mesh drawn in every view holding a material bound with `MaterialBinding::View`
+#[derive(Component)]
+pub struct ViewMaterialMesh<M: CustomMaterial>(PhantomData<M>);
+
+pub struct CustomMaterialPlugin<M: CustomMaterial>(PhantomData<M>);
+
+pub struct CustomPipeline<M: CustomMaterial> {
+    shader: Handle<Shader>,
+    pub mesh_pipeline: MeshPipeline,
+    pub bind_group_layouts: Vec<BindGroupLayout>,
+    marker: PhantomData<M>,
+}
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct CustomPipelineKey<K> {
+    pub mesh: MeshPipelineKey,
+    pub material: K,
+}
+
+pub type DrawCustomMaterial<M> = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetCustomMaterialBindGroup<M, 2>,
+    DrawMesh,
+);
+
+pub struct SetCustomMaterialBindGroup<M: CustomMaterial, const I: usize>(PhantomData<M>);
+
+/// Uniform buffer of each entity, kept across frames and rewritten in place
+/// instead of being created again for every new bind group
+pub struct EntityUniforms<T: AsStd140> {
+    uniforms: HashMap<Entity, UniformVec<T>>,
+}
+
+/// Bind group of each render world entity with the material, kept until the
+/// resources it binds change
+pub struct CustomMaterialBindGroups<M: CustomMaterial> {
+    bind_groups: HashMap<Entity, CachedBindGroup>,
+    marker: PhantomData<M>,
+}
+
+struct CachedBindGroup {
+    layout_index: usize,
+    resources: Vec<BoundResource>,
+    value: BindGroup,
+}
+
+/// `CustomMaterial::Uniforms` of each material
+struct CustomMaterialUniforms<M: CustomMaterial>(M::Uniforms);
+
+impl<M: CustomMaterial> Default for CustomMaterialPlugin<M> {
+    fn default() -> Self {
+        CustomMaterialPlugin(PhantomData)
+    }
+}
+
+impl<M: CustomMaterial> Plugin for CustomMaterialPlugin<M> {
+    fn build(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_render_command::<Opaque3d, DrawCustomMaterial<M>>()
+            .add_render_command::<Transparent3d, DrawCustomMaterial<M>>()
+            .init_resource::<CustomPipeline<M>>()
+            .init_resource::<SpecializedMeshPipelines<CustomPipeline<M>>>()
+            .init_resource::<CustomMaterialBindGroups<M>>()
+            .init_resource::<CustomMaterialUniforms<M>>()
+            .add_system_to_stage(RenderStage::Extract, extract_custom_materials::<M>)
+            .add_system_to_stage(RenderStage::Extract, extract_view_material_meshes::<M>)
+            .add_system_to_stage(RenderStage::Queue, queue_custom_material_bind_groups::<M>)
+            .add_system_to_stage(RenderStage::Queue, queue_custom_material_meshes::<M>);
+    }
+}
+
+impl<M: CustomMaterial> FromWorld for CustomPipeline<M> {
+    fn from_world(world: &mut World) -> Self {
+        let world = world.cell();
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let render_device = world.get_resource::<RenderDevice>().unwrap();
+        let mut mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap().clone();
+        mesh_pipeline.view_layout = world
+            .get_resource::<FogViewLayout>()
+            .unwrap()
+            .layout
+            .clone();
+
+        CustomPipeline {
+            shader: asset_server.load(M::SHADER),
+            mesh_pipeline,
+            bind_group_layouts: M::bind_group_layouts(render_device.as_ref()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<M: CustomMaterial> SpecializedMeshPipeline for CustomPipeline<M> {
+    type Key = CustomPipelineKey<M::Key>;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key.mesh, layout)?;
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.fragment = descriptor.fragment.map(|mut fragment| {
+            fragment.shader = self.shader.clone();
+            fragment
+        });
+        descriptor.layout = Some(vec![
+            self.mesh_pipeline.view_layout.clone(),
+            self.mesh_pipeline.mesh_layout.clone(),
+            self.bind_group_layouts[M::layout_index(key.material)].clone(),
+        ]);
+        M::specialize(key.material, &mut descriptor);
+        Ok(descriptor)
+    }
+}
+
+impl<M: CustomMaterial, const I: usize> EntityRenderCommand for SetCustomMaterialBindGroup<M, I> {
+    type Param = SRes<CustomMaterialBindGroups<M>>;
+
+    fn render<'w>(
+        view: Entity,
+        item: Entity,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let entity = match M::BINDING {
+            MaterialBinding::Entity => item,
+            MaterialBinding::View => view,
+        };
+        let Some(bind_group) = bind_groups.into_inner().bind_groups.get(&entity) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, &bind_group.value, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+impl<M: CustomMaterial> Default for CustomMaterialBindGroups<M> {
+    fn default() -> Self {
+        CustomMaterialBindGroups {
+            bind_groups: HashMap::default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<M: CustomMaterial> Default for CustomMaterialUniforms<M> {
+    fn default() -> Self {
+        CustomMaterialUniforms(M::Uniforms::default())
+    }
+}
+
+impl<M: CustomMaterial> Default for ViewMaterialMesh<M> {
+    fn default() -> Self {
+        ViewMaterialMesh(PhantomData)
+    }
+}
+
+impl From<&Buffer> for BoundResource {
+    fn from(buffer: &Buffer) -> Self {
+        BoundResource::Buffer(buffer.id())
+    }
+}
+
+impl From<&TextureView> for BoundResource {
+    fn from(texture_view: &TextureView) -> Self {
+        BoundResource::TextureView(texture_view.id())
+    }
+}
+
+impl From<&Sampler> for BoundResource {
+    fn from(sampler: &Sampler) -> Self {
+        BoundResource::Sampler(sampler.id())
+    }
+}
+
+impl<T: AsStd140> Default for EntityUniforms<T> {
+    fn default() -> Self {
+        EntityUniforms {
+            uniforms: HashMap::default(),
+        }
+    }
+}
+
+impl<T: AsStd140> EntityUniforms<T> {
+    /// Write `value` into the buffer of `entity`, only allocated the first time
+    pub fn write(
+        &mut self,
+        entity: Entity,
+        value: T,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) -> Option<BoundResource> {
+        let uniform = self.uniforms.entry(entity).or_default();
+        uniform.clear();
+        uniform.push(value);
+        uniform.write_buffer(render_device, render_queue);
+        uniform.uniform_buffer().map(BoundResource::from)
+    }
+
+    /// Buffer last written for `entity`
+    pub fn binding(&self, entity: Entity) -> Option<BindingResource> {
+        self.uniforms.get(&entity)?.binding()
+    }
+}
+
+impl<T: AsStd140 + Send + Sync + 'static> MaterialUniforms for EntityUniforms<T> {
+    fn retain(&mut self, keep: &dyn Fn(Entity) -> bool) {
+        self.uniforms.retain(|entity, _| keep(*entity));
+    }
+}
+
+impl MaterialUniforms for () {
+    fn retain(&mut self, _keep: &dyn Fn(Entity) -> bool) {}
+}
+
+impl<A: MaterialUniforms, B: MaterialUniforms> MaterialUniforms for (A, B) {
+    fn retain(&mut self, keep: &dyn Fn(Entity) -> bool) {
+        self.0.retain(keep);
+        self.1.retain(keep);
+    }
+}
+
+/// Materials keep their main world entity, cameras become the view entity
+fn extract_custom_materials<M: CustomMaterial>(
+    mut commands: Commands,
+    query: Query<(
+        Entity,
+        &M,
+        Option<&M::Companion>,
+        Option<&ComputedVisibility>,
+    )>,
+) {
+    for (entity, material, companion, visibility) in query.iter() {
+        if !visibility.map_or(true, |visibility| visibility.is_visible) {
+            continue;
+        }
+        let mut entity_commands = commands.get_or_spawn(entity);
+        entity_commands.insert(material.clone());
+        if let Some(companion) = companion {
+            entity_commands.insert(companion.clone());
+        }
+    }
+}
+
+fn extract_view_material_meshes<M: CustomMaterial>(
+    mut commands: Commands,
+    query: Query<Entity, With<ViewMaterialMesh<M>>>,
+) {
+    for entity in query.iter() {
+        commands
+            .get_or_spawn(entity)
+            .insert(ViewMaterialMesh::<M>::default());
+    }
+}
+
+/// Bind groups are only built again when the material binds other
+/// resources, those of entities that lost the material are freed
+#[allow(clippy::too_many_arguments)]
+fn queue_custom_material_bind_groups<M: CustomMaterial>(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    pipeline: Res<CustomPipeline<M>>,
+    mut bind_groups: ResMut<CustomMaterialBindGroups<M>>,
+    mut uniforms: ResMut<CustomMaterialUniforms<M>>,
+    materials: Query<(Entity, &M)>,
+    mut param: StaticSystemParam<M::Param>,
+) {
+    let uniforms = &mut uniforms.0;
+    for (entity, material) in materials.iter() {
+        let Some(resources) =
+            material.prepare(entity, &render_device, &render_queue, uniforms, &mut param)
+        else {
+            bind_groups.bind_groups.remove(&entity);
+            continue;
+        };
+        let layout_index = M::layout_index(material.key());
+        let is_cached = bind_groups
+            .bind_groups
+            .get(&entity)
+            .map_or(false, |cached| {
+                cached.layout_index == layout_index && cached.resources == resources
+            });
+        if is_cached {
+            continue;
+        }
+
+        match material.bind_group(entity, &render_device, &pipeline, uniforms, &mut param) {
+            Some(value) => {
+                bind_groups.bind_groups.insert(
+                    entity,
+                    CachedBindGroup {
+                        layout_index,
+                        resources,
+                        value,
+                    },
+                );
+            }
+            None => {
+                bind_groups.bind_groups.remove(&entity);
+            }
+        }
+    }
+
+    let current: HashSet<Entity> = materials.iter().map(|(entity, _)| entity).collect();
+    uniforms.retain(&|entity| current.contains(&entity));
+    bind_groups
+        .bind_groups
+        .retain(|entity, _| current.contains(entity));
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn queue_custom_material_meshes<M: CustomMaterial>(
+    opaque_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    transparent_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    custom_pipeline: Res<CustomPipeline<M>>,
+    msaa: Res<Msaa>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<CustomPipeline<M>>>,
+    mut pipeline_cache: ResMut<RenderPipelineCache>,
+    material_meshes: Query<(&M, &Handle<Mesh>, &MeshUniform)>,
+    view_material_meshes: Query<(Entity, &Handle<Mesh>, &MeshUniform), With<ViewMaterialMesh<M>>>,
+    mut views: Query<(
+        &ExtractedView,
+        &VisibleEntities,
+        Option<&M>,
+        &mut RenderPhase<Opaque3d>,
+        &mut RenderPhase<Transparent3d>,
+    )>,
+) {
+    let opaque_draw_function = opaque_draw_functions
+        .read()
+        .get_id::<DrawCustomMaterial<M>>()
+        .unwrap();
+    let transparent_draw_function = transparent_draw_functions
+        .read()
+        .get_id::<DrawCustomMaterial<M>>()
+        .unwrap();
+    let mut msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
+    if M::TRANSPARENT {
+        msaa_key |= MeshPipelineKey::TRANSPARENT_MAIN_PASS;
+    }
+
+    for (view, visible_entities, view_material, mut opaque_phase, mut transparent_phase) in
+        views.iter_mut()
+    {
+        let meshes: Vec<_> = match (&M::BINDING, view_material) {
+            (MaterialBinding::Entity, _) => visible_entities
+                .entities
+                .iter()
+                .filter_map(|&entity| {
+                    let (material, mesh_handle, mesh_uniform) = material_meshes.get(entity).ok()?;
+                    Some((entity, material, mesh_handle, mesh_uniform))
+                })
+                .collect(),
+            (MaterialBinding::View, Some(material)) => view_material_meshes
+                .iter()
+                .map(|(entity, mesh_handle, mesh_uniform)| {
+                    (entity, material, mesh_handle, mesh_uniform)
+                })
+                .collect(),
+            (MaterialBinding::View, None) => continue,
+        };
+
+        let view_row_2 = view.transform.compute_matrix().row(2);
+        for (entity, material, mesh_handle, mesh_uniform) in meshes {
+            let Some(render_mesh) = render_meshes.get(mesh_handle) else {
+                continue;
+            };
+            let key = CustomPipelineKey {
+                mesh: msaa_key
+                    | MeshPipelineKey::from_primitive_topology(render_mesh.primitive_topology),
+                material: material.key(),
+            };
+            let pipeline = match pipelines.specialize(
+                &mut pipeline_cache,
+                &custom_pipeline,
+                key,
+                &render_mesh.layout,
+            ) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+
+            let distance = M::sort_distance(view_row_2.dot(mesh_uniform.transform.col(3)));
+            if M::TRANSPARENT {
+                transparent_phase.add(Transparent3d {
+                    entity,
+                    pipeline,
+                    draw_function: transparent_draw_function,
+                    distance,
+                });
+            } else {
+                opaque_phase.add(Opaque3d {
+                    entity,
+                    pipeline,
+                    draw_function: opaque_draw_function,
+                    distance,
+                });
+            }
+        }
+    }
+}
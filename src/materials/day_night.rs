@@ -0,0 +1,180 @@
+use crate::editor::not_editing;
+use crate::materials::fog::DistanceFog;
+use crate::materials::irradiance::SkyIrradiance;
+use crate::materials::skybox::{SkyboxMaterial, SkyboxSettings};
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+/// Ambient light left at midnight, relative to the sky irradiance at noon
+const NIGHT_AMBIENT: f32 = 0.15;
+const SUN_ILLUMINANCE: f32 = 10000.0;
+const MOON_ILLUMINANCE: f32 = 400.0;
+/// Horizon of the atmosphere by day and by night in linear RGB, it has no
+/// irradiance to take the fog color from
+const ATMOSPHERE_HORIZON_DAY: [f32; 3] = [0.55, 0.65, 0.8];
+const ATMOSPHERE_HORIZON_NIGHT: [f32; 3] = [0.01, 0.012, 0.03];
+
+pub struct DayNightPlugin {
+    /// Length of a full day in seconds
+    pub day_length: f32,
+    /// Time of day the game starts at, see `TimeOfDay`
+    pub start: f32,
+}
+
+/// Fraction of the current day, 0 at midnight and 0.5 at noon. Only advances
+/// while racing, the editor freezes it
+pub struct TimeOfDay {
+    pub time: f32,
+    pub day_length: f32,
+}
+
+/// Ambient light at noon when it doesn't come from the sky irradiance, the
+/// `AmbientLight` set when the plugin is added by default
+pub struct BaseAmbientLight(pub AmbientLight);
+
+/// Directional light following the sun by day and the moon by night
+#[derive(Component)]
+pub struct CelestialLightMarker;
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        let base_ambient = app
+            .world
+            .get_resource::<AmbientLight>()
+            .cloned()
+            .unwrap_or_default();
+        app.insert_resource(BaseAmbientLight(base_ambient))
+            .insert_resource(TimeOfDay {
+                time: self.start,
+                day_length: self.day_length,
+            })
+            .add_startup_system(spawn_celestial_light)
+            .add_system(advance_time_of_day.with_run_criteria(not_editing))
+            .add_system(apply_time_of_day);
+    }
+}
+
+impl Default for DayNightPlugin {
+    fn default() -> Self {
+        DayNightPlugin {
+            day_length: 240.0,
+            start: 0.35,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Unit vector towards the sun, rising along +X at 0.25 and tilted
+    /// towards -Z so it's never straight overhead
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.time - 0.25) * TAU;
+        Vec3::new(angle.cos(), angle.sin(), -0.4).normalize()
+    }
+
+    /// Unit vector towards the moon, opposite the sun
+    pub fn moon_direction(&self) -> Vec3 {
+        -self.sun_direction()
+    }
+
+    /// 1 in full daylight, 0 at night, fading around sunrise and sunset
+    pub fn daylight(&self) -> f32 {
+        let height = self.sun_direction().y;
+        let t = ((height + 0.1) / 0.3).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+fn spawn_celestial_light(mut commands: Commands) {
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: SUN_ILLUMINANCE,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(CelestialLightMarker);
+}
+
+fn advance_time_of_day(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if time_of_day.day_length <= 0.0 {
+        return;
+    }
+    time_of_day.time = (time_of_day.time + time.delta_seconds() / time_of_day.day_length).fract();
+}
+
+/// Only writes what changed, the editor freezes the time of day
+#[allow(clippy::too_many_arguments)]
+fn apply_time_of_day(
+    time_of_day: Res<TimeOfDay>,
+    settings: Res<SkyboxSettings>,
+    base_ambient: Res<BaseAmbientLight>,
+    irradiance: Option<Res<SkyIrradiance>>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut fog: ResMut<DistanceFog>,
+    mut q_light: Query<(&mut Transform, &mut DirectionalLight), With<CelestialLightMarker>>,
+    mut q_skybox: Query<&mut SkyboxMaterial>,
+) {
+    let daylight = time_of_day.daylight();
+    let sun = time_of_day.sun_direction();
+    let moon = time_of_day.moon_direction();
+
+    for (mut transform, mut light) in q_light.iter_mut() {
+        // Lights shine along their forward axis, away from the body
+        let from = if daylight > 0.0 { sun } else { moon };
+        let light_transform = Transform::identity().looking_at(-from, Vec3::Y);
+        if *transform != light_transform {
+            *transform = light_transform;
+        }
+        let illuminance = if daylight > 0.0 {
+            SUN_ILLUMINANCE * daylight
+        } else {
+            MOON_ILLUMINANCE
+        };
+        if light.illuminance != illuminance {
+            light.illuminance = illuminance;
+        }
+    }
+
+    // Ambient and horizon are dimmed alike at night, models fade into the horizon
+    let sky = NIGHT_AMBIENT + (1.0 - NIGHT_AMBIENT) * daylight;
+    let (ambient_color, ambient_brightness) = match &irradiance {
+        Some(irradiance) if settings.ambient_from_sky => irradiance.ambient(),
+        _ => (base_ambient.0.color, base_ambient.0.brightness),
+    };
+    if ambient_light.color != ambient_color || ambient_light.brightness != ambient_brightness * sky
+    {
+        ambient_light.color = ambient_color;
+        ambient_light.brightness = ambient_brightness * sky;
+    }
+
+    let horizon = match &irradiance {
+        Some(irradiance) => irradiance.horizon() * sky,
+        None => {
+            Vec3::from(ATMOSPHERE_HORIZON_NIGHT).lerp(Vec3::from(ATMOSPHERE_HORIZON_DAY), daylight)
+        }
+    };
+    let fog_color = Color::rgb_linear(horizon.x, horizon.y, horizon.z);
+    if fog.color != fog_color {
+        fog.color = fog_color;
+    }
+
+    for mut material in q_skybox.iter_mut() {
+        let is_current = match &*material {
+            SkyboxMaterial::Texture { blend, .. } => *blend == 1.0 - daylight,
+            SkyboxMaterial::Atmosphere(atmosphere) => {
+                atmosphere.sun_direction == sun && atmosphere.moon_direction == moon
+            }
+        };
+        if is_current {
+            continue;
+        }
+        match material.as_mut() {
+            SkyboxMaterial::Texture { blend, .. } => *blend = 1.0 - daylight,
+            SkyboxMaterial::Atmosphere(atmosphere) => {
+                atmosphere.sun_direction = sun;
+                atmosphere.moon_direction = moon;
+            }
+        }
+    }
+}
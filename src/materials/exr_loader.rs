@@ -0,0 +1,72 @@
+//! OpenEXR textures, bevy only loads `.hdr` for HDR images. Sky panoramas
+//! saved as `.exr` are projected by `CubemapLayout::Equirectangular` like
+//! `.hdr` ones
+
+use anyhow::Result;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::Image;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use exr::prelude::*;
+use std::io::Cursor;
+
+/// Bytes of an `Rgba32Float` pixel
+const PIXEL_SIZE: usize = 4 * 4;
+
+/// Loads the first RGBA layer of `.exr` files as `Rgba32Float` images,
+/// images without alpha are opaque
+#[derive(Default)]
+pub struct ExrLoader;
+
+/// Pixels of the layer being read, rows of `width` pixels
+struct ExrPixels {
+    width: usize,
+    data: Vec<u8>,
+}
+
+impl AssetLoader for ExrLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let exr = read()
+                .no_deep_data()
+                .largest_resolution_level()
+                .rgba_channels(
+                    |resolution, _: &RgbaChannels| ExrPixels {
+                        width: resolution.width(),
+                        data: vec![0; resolution.width() * resolution.height() * PIXEL_SIZE],
+                    },
+                    |pixels: &mut ExrPixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                        let offset = (position.y() * pixels.width + position.x()) * PIXEL_SIZE;
+                        let channels = [r, g, b, a].map(f32::to_le_bytes);
+                        pixels.data[offset..offset + PIXEL_SIZE]
+                            .copy_from_slice(&channels.concat());
+                    },
+                )
+                .first_valid_layer()
+                .all_attributes()
+                .from_buffered(Cursor::new(bytes))?;
+
+            let size = exr.layer_data.size;
+            let image = Image::new(
+                Extent3d {
+                    width: size.width() as u32,
+                    height: size.height() as u32,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                exr.layer_data.channel_data.pixels.data,
+                TextureFormat::Rgba32Float,
+            );
+
+            load_context.set_default_asset(LoadedAsset::new(image));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["exr"]
+    }
+}
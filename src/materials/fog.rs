@@ -0,0 +1,352 @@
+//! Distance fog applied at the end of bevy's PBR fragment shader. The fog
+//! uniform is added to the mesh view bind group, every pipeline drawn with
+//! it has to use `FogViewLayout` instead of `MeshPipeline::view_layout`
+
+use crate::utils::manifest::Manifest;
+use bevy::pbr::{
+    GlobalLightMeta, GpuLights, LightMeta, MaterialPipeline, MeshPipeline, MeshViewBindGroup,
+    ShadowPipeline, ViewClusterBindings, ViewShadowBindings, PBR_SHADER_HANDLE,
+};
+use bevy::prelude::{
+    error, App, AssetEvent, Assets, Color, Commands, EventReader, FromWorld, Plugin, Query, Res,
+    ResMut, Shader, StandardMaterial, Vec4, World,
+};
+use bevy::render::render_resource::std140::AsStd140;
+use bevy::render::render_resource::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferSize,
+    SamplerBindingType, ShaderStages, TextureSampleType, TextureViewDimension, UniformVec,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::{ViewUniform, ViewUniforms};
+use bevy::render::{RenderApp, RenderStage};
+
+/// Fraction of the fog end distance the fog starts at
+const FOG_START: f32 = 2.0 / 3.0;
+/// Binding of the fog uniform in the mesh view bind group, after bevy's own
+pub const FOG_BINDING: u32 = 9;
+/// Entry point of bevy's PBR shader, wrapped by `pbr_fog.wgsl`
+const PBR_FRAGMENT: &str = "[[stage(fragment)]]\nfn fragment(";
+
+pub struct FogPlugin;
+
+/// Models fade into `color` between `start` and `end` distance from the
+/// camera, hiding them popping in at their max draw distance. `DayNightPlugin`
+/// keeps the color matching the horizon of the sky. Disabled while no
+/// manifest limits the draw distance
+#[derive(Clone)]
+pub struct DistanceFog {
+    pub color: Color,
+    pub start: f32,
+    pub end: f32,
+}
+
+#[derive(AsStd140)]
+struct FogUniform {
+    /// Alpha is how much fog there is, 0 while it's disabled
+    color: Vec4,
+    start: f32,
+    end: f32,
+}
+
+/// Mesh view layout with the fog uniform added
+pub struct FogViewLayout {
+    pub layout: BindGroupLayout,
+}
+
+#[derive(Default)]
+struct FogMeta {
+    uniform: UniformVec<FogUniform>,
+}
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DistanceFog>()
+            .add_system(fit_fog_to_draw_distance);
+
+        let mut shaders = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
+        let fogged = shaders
+            .get(PBR_SHADER_HANDLE)
+            .and_then(|shader| shader.get_wgsl_source())
+            .filter(|source| source.contains(PBR_FRAGMENT))
+            .map(|source| {
+                source.replace(PBR_FRAGMENT, "fn pbr_fragment(")
+                    + include_str!("../../assets/shaders/pbr_fog.wgsl")
+            });
+        match fogged {
+            Some(source) => shaders.set_untracked(PBR_SHADER_HANDLE, Shader::from_wgsl(source)),
+            None => error!("Couldn't find the fragment entry point of the PBR shader to fog it"),
+        }
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<FogViewLayout>();
+        let view_layout = render_app
+            .world
+            .get_resource::<FogViewLayout>()
+            .unwrap()
+            .layout
+            .clone();
+        render_app
+            .world
+            .get_resource_mut::<MaterialPipeline<StandardMaterial>>()
+            .unwrap()
+            .mesh_pipeline
+            .view_layout = view_layout;
+
+        render_app
+            .init_resource::<FogMeta>()
+            .add_system_to_stage(RenderStage::Extract, extract_distance_fog)
+            .add_system_to_stage(RenderStage::Prepare, prepare_fog_uniform)
+            .add_system_to_stage(RenderStage::PhaseSort, queue_fog_view_bind_groups);
+    }
+}
+
+impl Default for DistanceFog {
+    fn default() -> Self {
+        DistanceFog {
+            color: Color::rgb(0.02, 0.02, 0.06),
+            start: f32::INFINITY,
+            end: f32::INFINITY,
+        }
+    }
+}
+
+impl DistanceFog {
+    pub fn is_enabled(&self) -> bool {
+        self.end.is_finite()
+    }
+}
+
+impl FromWorld for FogViewLayout {
+    /// Same entries as bevy's mesh view layout, followed by the fog
+    fn from_world(world: &mut World) -> Self {
+        let world = world.cell();
+        let render_device = world.get_resource::<RenderDevice>().unwrap();
+        let mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap();
+
+        let clustered_binding_type = mesh_pipeline.clustered_forward_buffer_binding_type;
+        let clustered_min_binding_size = match clustered_binding_type {
+            BufferBindingType::Storage { .. } => None,
+            BufferBindingType::Uniform => BufferSize::new(16384),
+        };
+        let clustered_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: clustered_binding_type,
+                has_dynamic_offset: false,
+                min_binding_size: clustered_min_binding_size,
+            },
+            count: None,
+        };
+        let shadow_entries = |binding, view_dimension| {
+            [
+                BindGroupLayoutEntry {
+                    binding,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: binding + 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ]
+        };
+        let [point_shadows, point_shadow_sampler] =
+            shadow_entries(2, TextureViewDimension::CubeArray);
+        let [directional_shadows, directional_shadow_sampler] =
+            shadow_entries(4, TextureViewDimension::D2Array);
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("fog_mesh_view_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: BufferSize::new(ViewUniform::std140_size_static() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: BufferSize::new(GpuLights::std140_size_static() as u64),
+                    },
+                    count: None,
+                },
+                point_shadows,
+                point_shadow_sampler,
+                directional_shadows,
+                directional_shadow_sampler,
+                clustered_entry(6),
+                clustered_entry(7),
+                clustered_entry(8),
+                BindGroupLayoutEntry {
+                    binding: FOG_BINDING,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(FogUniform::std140_size_static() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        FogViewLayout { layout }
+    }
+}
+
+/// The fog ends at the largest max draw distance of the loaded manifests
+pub fn fit_fog_to_draw_distance(
+    mut fog: ResMut<DistanceFog>,
+    manifests: Res<Assets<Manifest>>,
+    mut manifest_events: EventReader<AssetEvent<Manifest>>,
+) {
+    if manifest_events.iter().next().is_none() {
+        return;
+    }
+
+    let end = manifests
+        .iter()
+        .filter_map(|(_, manifest)| manifest.lod.as_ref()?.max_distance)
+        .reduce(f32::max)
+        .unwrap_or(f32::INFINITY);
+    if fog.end != end {
+        fog.start = end * FOG_START;
+        fog.end = end;
+    }
+}
+
+fn extract_distance_fog(mut commands: Commands, fog: Res<DistanceFog>) {
+    commands.insert_resource(fog.clone());
+}
+
+fn prepare_fog_uniform(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    fog: Res<DistanceFog>,
+    mut fog_meta: ResMut<FogMeta>,
+) {
+    let [r, g, b, _] = fog.color.as_linear_rgba_f32();
+    let uniform = if fog.is_enabled() {
+        FogUniform {
+            color: Vec4::new(r, g, b, 1.0),
+            start: fog.start,
+            end: fog.end,
+        }
+    } else {
+        FogUniform {
+            color: Vec4::new(r, g, b, 0.0),
+            start: 0.0,
+            end: 1.0,
+        }
+    };
+
+    fog_meta.uniform.clear();
+    fog_meta.uniform.push(uniform);
+    fog_meta
+        .uniform
+        .write_buffer(render_device.as_ref(), render_queue.as_ref());
+}
+
+/// Replace the mesh view bind group bevy queued for each view with one
+/// holding the fog, bound the same way by `SetMeshViewBindGroup`
+#[allow(clippy::too_many_arguments)]
+fn queue_fog_view_bind_groups(
+    render_device: Res<RenderDevice>,
+    view_layout: Res<FogViewLayout>,
+    fog_meta: Res<FogMeta>,
+    shadow_pipeline: Res<ShadowPipeline>,
+    light_meta: Res<LightMeta>,
+    global_light_meta: Res<GlobalLightMeta>,
+    view_uniforms: Res<ViewUniforms>,
+    mut views: Query<(
+        &ViewShadowBindings,
+        &ViewClusterBindings,
+        &mut MeshViewBindGroup,
+    )>,
+) {
+    let (Some(view_binding), Some(light_binding), Some(point_light_binding), Some(fog_binding)) = (
+        view_uniforms.uniforms.binding(),
+        light_meta.view_gpu_lights.binding(),
+        global_light_meta.gpu_point_lights.binding(),
+        fog_meta.uniform.binding(),
+    ) else {
+        return;
+    };
+
+    for (shadow_bindings, cluster_bindings, mut mesh_view_bind_group) in views.iter_mut() {
+        let (Some(light_index_lists), Some(offsets_and_counts)) = (
+            cluster_bindings.light_index_lists_binding(),
+            cluster_bindings.offsets_and_counts_binding(),
+        ) else {
+            continue;
+        };
+
+        mesh_view_bind_group.value = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("fog_mesh_view_bind_group"),
+            layout: &view_layout.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: view_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: light_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(
+                        &shadow_bindings.point_light_depth_texture_view,
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&shadow_pipeline.point_light_sampler),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(
+                        &shadow_bindings.directional_light_depth_texture_view,
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::Sampler(&shadow_pipeline.directional_light_sampler),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: point_light_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: light_index_lists,
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: offsets_and_counts,
+                },
+                BindGroupEntry {
+                    binding: FOG_BINDING,
+                    resource: fog_binding.clone(),
+                },
+            ],
+        });
+    }
+}
@@ -0,0 +1,608 @@
+use crate::materials::fog::FogViewLayout;
+use crate::utils::scene_meshes::{scene_meshes, SceneMesh};
+use bevy::core_pipeline::Opaque3d;
+use bevy::ecs::system::lifetimeless::{Read, SQuery, SRes};
+use bevy::ecs::system::SystemParamItem;
+use bevy::gltf::Gltf;
+use bevy::pbr::{
+    MaterialPipeline, MeshPipeline, MeshPipelineKey, MeshUniform, SetMaterialBindGroup,
+    SetMeshViewBindGroup, SetShadowViewBindGroup, Shadow, ShadowPipeline, ShadowPipelineKey,
+    ViewLightEntities, PBR_SHADER_HANDLE, SHADOW_FORMAT,
+};
+use bevy::prelude::{
+    error, App, AssetEvent, AssetServer, Assets, Commands, Component, CoreStage, Entity,
+    EventReader, FromWorld, GlobalTransform, Handle, Mat4, Mesh, Msaa,
+    ParallelSystemDescriptorCoercion, Plugin, Query, Res, ResMut, Scene, Shader, StandardMaterial,
+    Vec4, Visibility, With, World,
+};
+use bevy::render::mesh::{GpuBufferInfo, MeshVertexBufferLayout};
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
+    SetItemPipeline, TrackedRenderPass,
+};
+use bevy::render::render_resource::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BufferUsages, BufferVec,
+    CompareFunction, DepthBiasState, DepthStencilState, Face, FrontFace, MultisampleState,
+    PolygonMode, PrimitiveState, RenderPipelineCache, RenderPipelineDescriptor,
+    SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+    StencilFaceState, StencilState, UniformVec, VertexAttribute, VertexBufferLayout, VertexFormat,
+    VertexState, VertexStepMode,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::ExtractedView;
+use bevy::render::{RenderApp, RenderStage};
+use bevy::transform::TransformSystem;
+use bevy::utils::{HashMap, HashSet};
+use bytemuck::{Pod, Zeroable};
+
+/// First shader location of the per-instance attributes, the ones before are
+/// used by the mesh vertex attributes
+const INSTANCE_SHADER_LOCATION: u32 = 8;
+/// `MESH_FLAGS_SHADOW_RECEIVER_BIT` of bevy_pbr's mesh.wgsl
+const MESH_FLAGS_SHADOW_RECEIVER: u32 = 1;
+
+pub struct InstancingPlugin;
+
+/// Model drawn in batches with every other instance of the same meshes,
+/// instead of spawning its scene
+#[derive(Component)]
+pub struct InstancedModel {
+    pub gltf: Handle<Gltf>,
+}
+
+type BatchKey = (Handle<Mesh>, Handle<StandardMaterial>);
+
+/// World transforms of every visible instance, per mesh and material pair
+#[derive(Default)]
+pub struct InstanceBatches {
+    batches: HashMap<BatchKey, Vec<Mat4>>,
+    /// Mesh primitives of each instanced model, placed by their node transforms
+    models: HashMap<Handle<Gltf>, Vec<SceneMesh>>,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct InstanceData {
+    model: [[f32; 4]; 4],
+}
+
+/// Extracted batch, drawn along with the `Handle<Mesh>` and
+/// `Handle<StandardMaterial>` on the same entity
+#[derive(Component)]
+pub struct InstanceBatch {
+    instances: Vec<InstanceData>,
+}
+
+/// Instance buffers kept across frames, they only get reallocated when their
+/// batch outgrows them
+#[derive(Default)]
+pub struct InstanceBuffers {
+    buffers: HashMap<BatchKey, BufferVec<InstanceData>>,
+}
+
+/// Mesh uniform shared by every batch, instances carry their own transform
+#[derive(Default)]
+pub struct InstanceMeshMeta {
+    uniform: UniformVec<MeshUniform>,
+    bind_group: Option<BindGroup>,
+}
+
+/// Shaded by the `StandardMaterial` fragment shader like any other mesh
+pub type InstancedDrawCustom = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMaterialBindGroup<StandardMaterial, 1>,
+    SetInstanceMeshBindGroup<2>,
+    DrawMeshInstanced,
+);
+
+pub type DrawInstancedShadow = (
+    SetItemPipeline,
+    SetShadowViewBindGroup<0>,
+    DrawMeshInstanced,
+);
+
+pub struct InstancingPipeline {
+    shader: Handle<Shader>,
+    pub mesh_pipeline: MeshPipeline,
+    material_layout: BindGroupLayout,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstancingPipelineKey {
+    mesh: MeshPipelineKey,
+    normal_map: bool,
+    cull_mode: Option<Face>,
+}
+
+/// Depth only pipeline drawing instances into the shadow maps
+pub struct InstancedShadowPipeline {
+    shader: Handle<Shader>,
+    view_layout: BindGroupLayout,
+}
+
+pub struct SetInstanceMeshBindGroup<const I: usize>;
+
+pub struct DrawMeshInstanced;
+
+impl Plugin for InstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InstanceBatches>().add_system_to_stage(
+            CoreStage::PostUpdate,
+            batch_instances.after(TransformSystem::TransformPropagate),
+        );
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_render_command::<Opaque3d, InstancedDrawCustom>()
+            .add_render_command::<Shadow, DrawInstancedShadow>()
+            .init_resource::<InstancingPipeline>()
+            .init_resource::<SpecializedMeshPipelines<InstancingPipeline>>()
+            .init_resource::<InstancedShadowPipeline>()
+            .init_resource::<SpecializedMeshPipelines<InstancedShadowPipeline>>()
+            .init_resource::<InstanceBuffers>()
+            .init_resource::<InstanceMeshMeta>()
+            .add_system_to_stage(RenderStage::Extract, extract_instance_batches)
+            .add_system_to_stage(RenderStage::Prepare, prepare_instance_buffers)
+            .add_system_to_stage(RenderStage::Prepare, prepare_instance_mesh_uniform)
+            .add_system_to_stage(RenderStage::Queue, queue_instanced_pipeline)
+            .add_system_to_stage(RenderStage::Queue, queue_instanced_shadows);
+    }
+}
+
+impl FromWorld for InstancingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let world = world.cell();
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let shader = asset_server.load("shaders/instancing.wgsl");
+
+        let mut mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap().clone();
+        // Shaded by the fogged PBR shader
+        mesh_pipeline.view_layout = world
+            .get_resource::<FogViewLayout>()
+            .unwrap()
+            .layout
+            .clone();
+        let material_pipeline = world
+            .get_resource::<MaterialPipeline<StandardMaterial>>()
+            .unwrap();
+
+        InstancingPipeline {
+            shader,
+            mesh_pipeline,
+            material_layout: material_pipeline.material_layout.clone(),
+        }
+    }
+}
+
+impl FromWorld for InstancedShadowPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let world = world.cell();
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let shadow_pipeline = world.get_resource::<ShadowPipeline>().unwrap();
+
+        InstancedShadowPipeline {
+            shader: asset_server.load("shaders/instancing.wgsl"),
+            view_layout: shadow_pipeline.view_layout.clone(),
+        }
+    }
+}
+
+/// Model matrix of each instance, one column per attribute
+fn instance_buffer_layout() -> VertexBufferLayout {
+    let column_size = VertexFormat::Float32x4.size();
+    VertexBufferLayout {
+        array_stride: std::mem::size_of::<InstanceData>() as u64,
+        step_mode: VertexStepMode::Instance,
+        attributes: (0..4)
+            .map(|i| VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: i as u64 * column_size,
+                shader_location: INSTANCE_SHADER_LOCATION + i,
+            })
+            .collect(),
+    }
+}
+
+impl SpecializedMeshPipeline for InstancingPipeline {
+    type Key = InstancingPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key.mesh, layout)?;
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers.push(instance_buffer_layout());
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = PBR_SHADER_HANDLE.typed::<Shader>();
+            if key.normal_map {
+                fragment
+                    .shader_defs
+                    .push(String::from("STANDARDMATERIAL_NORMAL_MAP"));
+            }
+        }
+        descriptor.primitive.cull_mode = key.cull_mode;
+        descriptor.layout = Some(vec![
+            self.mesh_pipeline.view_layout.clone(),
+            self.material_layout.clone(),
+            self.mesh_pipeline.mesh_layout.clone(),
+        ]);
+        Ok(descriptor)
+    }
+}
+
+impl SpecializedMeshPipeline for InstancedShadowPipeline {
+    type Key = ShadowPipelineKey;
+
+    /// Same as bevy's shadow pipeline, with the model matrix from the instance buffer
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let vertex_buffer_layout =
+            layout.get_layout(&[Mesh::ATTRIBUTE_POSITION.at_shader_location(0)])?;
+
+        Ok(RenderPipelineDescriptor {
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                entry_point: "vertex_depth".into(),
+                shader_defs: Vec::new(),
+                buffers: vec![vertex_buffer_layout, instance_buffer_layout()],
+            },
+            fragment: None,
+            layout: Some(vec![self.view_layout.clone()]),
+            primitive: PrimitiveState {
+                topology: key.primitive_topology(),
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState {
+                    front: StencilFaceState::IGNORE,
+                    back: StencilFaceState::IGNORE,
+                    read_mask: 0,
+                    write_mask: 0,
+                },
+                bias: DepthBiasState {
+                    constant: 0,
+                    slope_scale: 0.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: MultisampleState::default(),
+            label: Some("instanced_shadow_pipeline".into()),
+        })
+    }
+}
+
+impl<const I: usize> EntityRenderCommand for SetInstanceMeshBindGroup<I> {
+    type Param = SRes<InstanceMeshMeta>;
+
+    fn render<'w>(
+        _view: Entity,
+        _item: Entity,
+        mesh_meta: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = mesh_meta.into_inner().bind_group.as_ref() else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, bind_group, &[0]);
+        RenderCommandResult::Success
+    }
+}
+
+impl EntityRenderCommand for DrawMeshInstanced {
+    type Param = (
+        SRes<RenderAssets<Mesh>>,
+        SRes<InstanceBuffers>,
+        SQuery<(Read<Handle<Mesh>>, Read<Handle<StandardMaterial>>)>,
+    );
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        (meshes, instance_buffers, q_batch): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Ok((mesh_handle, material_handle)) = q_batch.get(item) else {
+            return RenderCommandResult::Failure;
+        };
+        let key = (mesh_handle.clone_weak(), material_handle.clone_weak());
+        let Some(instance_buffer) = instance_buffers.into_inner().buffers.get(&key) else {
+            return RenderCommandResult::Failure;
+        };
+        let (Some(buffer), Some(gpu_mesh)) = (
+            instance_buffer.buffer(),
+            meshes.into_inner().get(mesh_handle),
+        ) else {
+            return RenderCommandResult::Failure;
+        };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, buffer.slice(..));
+
+        let instances = 0..instance_buffer.len() as u32;
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed {
+                buffer,
+                index_format,
+                count,
+            } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, instances);
+            }
+            GpuBufferInfo::NonIndexed { vertex_count } => {
+                pass.draw(0..*vertex_count, instances);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
+
+/// Group instanced models by the mesh primitives of their `gltf`, placed
+/// through the node hierarchy of the scene they would otherwise spawn
+pub fn batch_instances(
+    mut batches: ResMut<InstanceBatches>,
+    mut gltf_events: EventReader<AssetEvent<Gltf>>,
+    gltfs: Res<Assets<Gltf>>,
+    scenes: Res<Assets<Scene>>,
+    q_instances: Query<(&GlobalTransform, &InstancedModel, Option<&Visibility>)>,
+) {
+    let InstanceBatches { batches, models } = batches.as_mut();
+
+    // Reloaded models get their meshes collected again
+    for event in gltf_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            models.remove(handle);
+        }
+    }
+
+    for transforms in batches.values_mut() {
+        transforms.clear();
+    }
+
+    for (transform, model, visibility) in q_instances.iter() {
+        if !visibility.map_or(true, |visibility| visibility.is_visible) {
+            continue;
+        }
+        if !models.contains_key(&model.gltf) {
+            let Some(meshes) = gltfs
+                .get(&model.gltf)
+                .and_then(|gltf| scene_meshes(gltf, &scenes))
+            else {
+                continue;
+            };
+            models.insert(model.gltf.clone_weak(), meshes);
+        }
+
+        let model_matrix = transform.compute_matrix();
+        for scene_mesh in models[&model.gltf].iter() {
+            let material = scene_mesh.material.clone().unwrap_or_default();
+            batches
+                .entry((scene_mesh.mesh.clone(), material))
+                .or_default()
+                .push(model_matrix * scene_mesh.transform);
+        }
+    }
+
+    // Drop batches of despawned models
+    batches.retain(|_, transforms| !transforms.is_empty());
+}
+
+fn extract_instance_batches(mut commands: Commands, batches: Res<InstanceBatches>) {
+    let extracted: Vec<_> = batches
+        .batches
+        .iter()
+        .map(|((mesh, material), transforms)| {
+            let instances = transforms
+                .iter()
+                .map(|transform| InstanceData {
+                    model: transform.to_cols_array_2d(),
+                })
+                .collect();
+
+            (
+                mesh.clone_weak(),
+                material.clone_weak(),
+                InstanceBatch { instances },
+            )
+        })
+        .collect();
+
+    commands.spawn_batch(extracted);
+}
+
+/// Write each batch into its buffer from the last frames, growing it if needed
+fn prepare_instance_buffers(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut instance_buffers: ResMut<InstanceBuffers>,
+    q_batches: Query<(&Handle<Mesh>, &Handle<StandardMaterial>, &InstanceBatch)>,
+) {
+    let mut drawn = HashSet::default();
+    for (mesh, material, batch) in q_batches.iter() {
+        let key = (mesh.clone_weak(), material.clone_weak());
+        let buffer = instance_buffers
+            .buffers
+            .entry(key.clone())
+            .or_insert_with(|| BufferVec::new(BufferUsages::VERTEX));
+
+        buffer.clear();
+        for instance in batch.instances.iter() {
+            buffer.push(*instance);
+        }
+        buffer.write_buffer(render_device.as_ref(), render_queue.as_ref());
+        drawn.insert(key);
+    }
+
+    // Free the buffers of batches that are gone
+    instance_buffers
+        .buffers
+        .retain(|key, _| drawn.contains(key));
+}
+
+/// Every batch binds the same identity mesh uniform, written once
+fn prepare_instance_mesh_uniform(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    instancing_pipeline: Res<InstancingPipeline>,
+    mut mesh_meta: ResMut<InstanceMeshMeta>,
+) {
+    if mesh_meta.bind_group.is_some() {
+        return;
+    }
+
+    mesh_meta.uniform.push(MeshUniform {
+        transform: Mat4::IDENTITY,
+        inverse_transpose_model: Mat4::IDENTITY,
+        flags: MESH_FLAGS_SHADOW_RECEIVER,
+    });
+    mesh_meta
+        .uniform
+        .write_buffer(render_device.as_ref(), render_queue.as_ref());
+    let Some(binding) = mesh_meta.uniform.binding() else {
+        return;
+    };
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("instance_mesh_bind_group"),
+        layout: &instancing_pipeline.mesh_pipeline.mesh_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: binding,
+        }],
+    });
+    mesh_meta.bind_group = Some(bind_group);
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn queue_instanced_pipeline(
+    opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    instancing_pipeline: Res<InstancingPipeline>,
+    msaa: Res<Msaa>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    render_materials: Res<RenderAssets<StandardMaterial>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<InstancingPipeline>>,
+    mut pipeline_cache: ResMut<RenderPipelineCache>,
+    q_batches: Query<(
+        Entity,
+        &Handle<Mesh>,
+        &Handle<StandardMaterial>,
+        &InstanceBatch,
+    )>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
+) {
+    let draw_function = opaque_3d_draw_functions
+        .read()
+        .get_id::<InstancedDrawCustom>()
+        .unwrap();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
+
+    for (view, mut opaque_phase) in views.iter_mut() {
+        let view_row_2 = view.transform.compute_matrix().row(2);
+        for (entity, mesh_handle, material_handle, batch) in q_batches.iter() {
+            // The material bind group can only be set once the material is prepared
+            let (Some(render_mesh), Some(material)) = (
+                render_meshes.get(mesh_handle),
+                render_materials.get(material_handle),
+            ) else {
+                continue;
+            };
+            let mesh_key =
+                msaa_key | MeshPipelineKey::from_primitive_topology(render_mesh.primitive_topology);
+            let key = InstancingPipelineKey {
+                mesh: mesh_key,
+                normal_map: material.has_normal_map,
+                cull_mode: material.cull_mode,
+            };
+            let pipeline = match pipelines.specialize(
+                &mut pipeline_cache,
+                &instancing_pipeline,
+                key,
+                &render_mesh.layout,
+            ) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Couldn't specialize instancing pipeline: {}", err);
+                    continue;
+                }
+            };
+
+            // Batches are spread along the whole track, sort them by their first instance
+            let position = batch
+                .instances
+                .first()
+                .map_or(Vec4::W, |instance| Vec4::from(instance.model[3]));
+            let distance = view_row_2.dot(position);
+            opaque_phase.add(Opaque3d {
+                entity,
+                pipeline,
+                draw_function,
+                distance,
+            });
+        }
+    }
+}
+
+/// Draw every batch into the shadow maps of the lights casting shadows
+#[allow(clippy::too_many_arguments)]
+fn queue_instanced_shadows(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    shadow_pipeline: Res<InstancedShadowPipeline>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<InstancedShadowPipeline>>,
+    mut pipeline_cache: ResMut<RenderPipelineCache>,
+    q_batches: Query<(Entity, &Handle<Mesh>), With<InstanceBatch>>,
+    q_view_lights: Query<&ViewLightEntities>,
+    mut q_shadow_phases: Query<&mut RenderPhase<Shadow>>,
+) {
+    let draw_function = shadow_draw_functions
+        .read()
+        .get_id::<DrawInstancedShadow>()
+        .unwrap();
+
+    for view_lights in q_view_lights.iter() {
+        for light_entity in view_lights.lights.iter().copied() {
+            let Ok(mut shadow_phase) = q_shadow_phases.get_mut(light_entity) else {
+                continue;
+            };
+            for (entity, mesh_handle) in q_batches.iter() {
+                let Some(render_mesh) = render_meshes.get(mesh_handle) else {
+                    continue;
+                };
+                let key =
+                    ShadowPipelineKey::from_primitive_topology(render_mesh.primitive_topology);
+                let pipeline = match pipelines.specialize(
+                    &mut pipeline_cache,
+                    &shadow_pipeline,
+                    key,
+                    &render_mesh.layout,
+                ) {
+                    Ok(pipeline) => pipeline,
+                    Err(err) => {
+                        error!("Couldn't specialize instanced shadow pipeline: {}", err);
+                        continue;
+                    }
+                };
+
+                shadow_phase.add(Shadow {
+                    draw_function,
+                    pipeline,
+                    entity,
+                    distance: 0.0,
+                });
+            }
+        }
+    }
+}
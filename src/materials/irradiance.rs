@@ -0,0 +1,209 @@
+//! Diffuse lighting of the sky, projected on the first 3 bands of spherical
+//! harmonics from the converted skybox array
+
+use crate::materials::cubemap::{face_direction, read_pixel};
+use bevy::prelude::{Color, Image, Vec3};
+use bevy::render::render_resource::TextureFormat;
+use std::f32::consts::PI;
+
+/// Cosine lobe convolution factor of each band
+const BAND_FACTORS: [f32; 3] = [PI, 2.0 * PI / 3.0, PI / 4.0];
+
+/// Irradiance coefficients in linear RGB, in the usual `l, m` order
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SkyIrradiance {
+    pub coefficients: [Vec3; 9],
+}
+
+fn sh_basis(n: Vec3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * n.y,
+        0.488603 * n.z,
+        0.488603 * n.x,
+        1.092548 * n.x * n.y,
+        1.092548 * n.y * n.z,
+        0.315392 * (3.0 * n.z * n.z - 1.0),
+        1.092548 * n.x * n.z,
+        0.546274 * (n.x * n.x - n.y * n.y),
+    ]
+}
+
+fn band(coefficient: usize) -> usize {
+    match coefficient {
+        0 => 0,
+        1..=3 => 1,
+        _ => 2,
+    }
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl SkyIrradiance {
+    /// Project every texel of a converted 6-layer skybox, weighted by the
+    /// solid angle it covers. Fails on compressed textures
+    pub fn from_cubemap(image: &Image) -> Result<Self, String> {
+        let size = image.texture_descriptor.size.width;
+        let is_srgb = image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb;
+
+        let mut coefficients = [Vec3::ZERO; 9];
+        let mut total_weight = 0.0;
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let u = (x as f32 + 0.5) / size as f32;
+                    let v = (y as f32 + 0.5) / size as f32;
+                    let direction = Vec3::from(face_direction(face, u, v));
+                    // Texel area on the unit cube projected on the sphere
+                    let weight = direction.length_squared().powf(-1.5);
+
+                    let [r, g, b, _] = read_pixel(image, x, face as u32 * size + y)?;
+                    let mut color = Vec3::new(r, g, b);
+                    if is_srgb {
+                        color = Vec3::new(
+                            srgb_to_linear(color.x),
+                            srgb_to_linear(color.y),
+                            srgb_to_linear(color.z),
+                        );
+                    }
+
+                    let basis = sh_basis(direction.normalize());
+                    for (coefficient, basis) in coefficients.iter_mut().zip(basis) {
+                        *coefficient += color * basis * weight;
+                    }
+                    total_weight += weight;
+                }
+            }
+        }
+
+        if total_weight > 0.0 {
+            let normalization = 4.0 * PI / total_weight;
+            for (i, coefficient) in coefficients.iter_mut().enumerate() {
+                *coefficient *= normalization * BAND_FACTORS[band(i)];
+            }
+        }
+
+        Ok(SkyIrradiance { coefficients })
+    }
+
+    /// Irradiance reaching a surface facing `normal`
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        self.coefficients
+            .iter()
+            .zip(sh_basis(normal.normalize()))
+            .map(|(coefficient, basis)| *coefficient * basis)
+            .fold(Vec3::ZERO, |sum, term| sum + term)
+            .max(Vec3::ZERO)
+    }
+
+    /// Average radiance of the sky towards the horizon
+    pub fn horizon(&self) -> Vec3 {
+        let irradiance = [Vec3::X, Vec3::Z, -Vec3::X, -Vec3::Z]
+            .into_iter()
+            .map(|direction| self.irradiance(direction))
+            .fold(Vec3::ZERO, |sum, irradiance| sum + irradiance);
+        irradiance / (4.0 * PI)
+    }
+
+    /// Ambient light color and brightness matching the sky's average irradiance
+    pub fn ambient(&self) -> (Color, f32) {
+        let radiance = self.coefficients[0] * sh_basis(Vec3::Y)[0] / PI;
+        let brightness = radiance.max_element();
+        if brightness <= 0.0 {
+            return (Color::BLACK, 0.0);
+        }
+
+        let color = radiance / brightness;
+        (Color::rgb_linear(color.x, color.y, color.z), brightness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::Vec4;
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    /// Converted 6-layer float skybox with the radiance `sky` gives every
+    /// direction
+    fn float_skybox(size: u32, sky: impl Fn(Vec3) -> f32) -> Image {
+        let mut data = Vec::new();
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let u = (x as f32 + 0.5) / size as f32;
+                    let v = (y as f32 + 0.5) / size as f32;
+                    let radiance = sky(Vec3::from(face_direction(face, u, v)).normalize());
+                    for channel in [radiance, radiance, radiance, 1.0] {
+                        data.extend_from_slice(&channel.to_le_bytes());
+                    }
+                }
+            }
+        }
+        let mut image = Image::new(
+            Extent3d {
+                width: size,
+                height: size * 6,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba32Float,
+        );
+        image.reinterpret_stacked_2d_as_array(6);
+        image
+    }
+
+    #[test]
+    fn sh_basis_order() {
+        // Band 1 is y, z, x
+        let x = sh_basis(Vec3::X);
+        let y = sh_basis(Vec3::Y);
+        let z = sh_basis(Vec3::Z);
+        assert!(y[1] > 0.0 && y[2] == 0.0 && y[3] == 0.0);
+        assert!(z[1] == 0.0 && z[2] > 0.0 && z[3] == 0.0);
+        assert!(x[1] == 0.0 && x[2] == 0.0 && x[3] > 0.0);
+
+        // Band 2 is xy, yz, 3z² - 1, xz, x² - y²
+        let xy = sh_basis(Vec3::new(1.0, 1.0, 0.0).normalize());
+        let yz = sh_basis(Vec3::new(0.0, 1.0, 1.0).normalize());
+        let xz = sh_basis(Vec3::new(1.0, 0.0, 1.0).normalize());
+        assert!(xy[4] > 0.0 && xy[5] == 0.0 && xy[7] == 0.0);
+        assert!(yz[4] == 0.0 && yz[5] > 0.0 && yz[7] == 0.0);
+        assert!(xz[4] == 0.0 && xz[5] == 0.0 && xz[7] > 0.0);
+        assert!(z[6] > 0.0 && x[6] < 0.0 && y[6] < 0.0);
+        assert!(x[8] > 0.0 && y[8] < 0.0 && z[8] == 0.0);
+    }
+
+    #[test]
+    fn uniform_sky() {
+        let irradiance = SkyIrradiance::from_cubemap(&float_skybox(8, |_| 1.0)).unwrap();
+
+        for normal in [Vec3::X, Vec3::Y, -Vec3::Z, Vec3::new(1.0, -2.0, 3.0)] {
+            let received = irradiance.irradiance(normal);
+            assert!((received - Vec3::splat(PI)).abs().max_element() < 1e-3);
+        }
+        let (color, brightness) = irradiance.ambient();
+        assert!((brightness - 1.0).abs() < 1e-3);
+        assert!((Vec4::from(color) - Vec4::ONE).abs().max_element() < 1e-3);
+    }
+
+    #[test]
+    fn sky_lit_from_above() {
+        let irradiance =
+            SkyIrradiance::from_cubemap(&float_skybox(16, |direction| direction.y.max(0.0)))
+                .unwrap();
+
+        let [_, y, z, x, ..] = irradiance.coefficients;
+        assert!(y.x > 0.0);
+        assert!(z.x.abs() < 1e-3 && x.x.abs() < 1e-3);
+        assert!(irradiance.irradiance(Vec3::Y).x > irradiance.irradiance(Vec3::X).x);
+        assert!(irradiance.irradiance(Vec3::X).x > irradiance.irradiance(-Vec3::Y).x);
+    }
+}
@@ -1 +1,9 @@
+pub mod cubemap;
+pub mod custom;
+pub mod day_night;
+pub mod exr_loader;
+pub mod fog;
+pub mod instancing;
+pub mod irradiance;
+pub mod post_processing;
 pub mod skybox;
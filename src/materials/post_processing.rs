@@ -0,0 +1,389 @@
+//! Screen effects reacting to the ship's speed. The main camera renders into
+//! `PostProcessTarget`, which `PostProcessNode` draws to the window after the
+//! main pass with the effects applied and before the UI is drawn over it.
+//! Only the main camera is post-processed, other cameras drawing to the
+//! primary window are covered by it
+
+use crate::entities::ship::{PlayerShipDescriptor, PlayerShipMarker, TERMINAL_VELOCITY_Z};
+use crate::utils::local_settings::LocalSettingsLoader;
+use crate::MainCameraMarker;
+use bevy::core_pipeline::node::MAIN_PASS_DRIVER;
+use bevy::prelude::{
+    Added, App, AssetServer, Assets, Camera, Commands, EventReader, FromWorld, Handle, Image,
+    Plugin, Query, Res, ResMut, Time, Windows, With, World,
+};
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext};
+use bevy::render::render_resource::std140::AsStd140;
+use bevy::render::render_resource::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferSize,
+    CachedPipelineId, ColorTargetState, ColorWrites, Extent3d, FragmentState, LoadOp,
+    MultisampleState, Operations, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
+    RenderPipelineCache, RenderPipelineDescriptor, SamplerBindingType, ShaderStages,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureViewDimension, UniformVec, VertexState,
+};
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy::render::texture::BevyDefault;
+use bevy::render::view::ExtractedWindows;
+use bevy::render::{RenderApp, RenderStage};
+use bevy::ui::node::UI_PASS_DRIVER;
+use bevy::window::{WindowId, WindowResized};
+use bevy_rapier3d::prelude::RigidBodyVelocityComponent;
+
+pub const POST_PROCESS_NODE: &str = "post_process";
+
+/// Vignette strength at speed level 0, and how much each level adds
+const VIGNETTE_BASE: f32 = 0.2;
+const VIGNETTE_PER_LEVEL: f32 = 0.08;
+const VIGNETTE_MAX: f32 = 0.7;
+/// Fraction of the distance to the screen center blurred at top speed
+const MOTION_BLUR_MAX: f32 = 0.06;
+/// How fast effects catch up with the ship's speed, per second
+const EFFECT_RESPONSE: f32 = 4.0;
+
+pub struct PostProcessingPlugin;
+
+/// Scene rendered by the main camera, sized like the primary window
+pub struct PostProcessTarget {
+    pub image: Handle<Image>,
+}
+
+/// Current strength of the effects, 0 disables them
+#[derive(Clone, Default)]
+pub struct PostProcessSettings {
+    pub vignette: f32,
+    pub motion_blur: f32,
+}
+
+#[derive(AsStd140)]
+struct PostProcessUniform {
+    vignette: f32,
+    motion_blur: f32,
+}
+
+struct ExtractedPostProcess {
+    image: Handle<Image>,
+    settings: PostProcessSettings,
+}
+
+pub struct PostProcessPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedPipelineId,
+}
+
+#[derive(Default)]
+struct PostProcessBindGroup {
+    value: Option<BindGroup>,
+    /// Written every frame, the buffer is only created once
+    uniform: UniformVec<PostProcessUniform>,
+}
+
+/// Draws the post-processed scene over the primary window, the UI pass
+/// draws on top of it
+pub struct PostProcessNode;
+
+impl Plugin for PostProcessingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PostProcessSettings>()
+            .add_startup_system(setup_target)
+            .add_system(render_main_camera_to_target)
+            .add_system(resize_target)
+            .add_system(update_post_process_settings);
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .init_resource::<PostProcessPipeline>()
+            .init_resource::<PostProcessBindGroup>()
+            .add_system_to_stage(RenderStage::Extract, extract_post_process)
+            .add_system_to_stage(RenderStage::Queue, queue_post_process_bind_group);
+
+        let mut graph = render_app.world.get_resource_mut::<RenderGraph>().unwrap();
+        graph.add_node(POST_PROCESS_NODE, PostProcessNode);
+        graph
+            .add_node_edge(MAIN_PASS_DRIVER, POST_PROCESS_NODE)
+            .unwrap();
+        graph
+            .add_node_edge(POST_PROCESS_NODE, UI_PASS_DRIVER)
+            .unwrap();
+    }
+}
+
+impl FromWorld for PostProcessPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world
+            .get_resource::<AssetServer>()
+            .unwrap()
+            .load("shaders/post_processing.wgsl");
+
+        let render_device = world.get_resource::<RenderDevice>().unwrap();
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("post process bind group"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(
+                            PostProcessUniform::std140_size_static() as u64
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let descriptor = RenderPipelineDescriptor {
+            label: Some("post_process_pipeline".into()),
+            layout: Some(vec![layout.clone()]),
+            // Fullscreen triangle generated from the vertex index
+            vertex: VertexState {
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader,
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+        };
+        let pipeline = world
+            .get_resource_mut::<RenderPipelineCache>()
+            .unwrap()
+            .queue(descriptor);
+
+        PostProcessPipeline { layout, pipeline }
+    }
+}
+
+impl Node for PostProcessNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let post_process_pipeline = world.get_resource::<PostProcessPipeline>().unwrap();
+        let pipeline_cache = world.get_resource::<RenderPipelineCache>().unwrap();
+        let bind_group = world.get_resource::<PostProcessBindGroup>().unwrap();
+        let windows = world.get_resource::<ExtractedWindows>().unwrap();
+
+        // Nothing to draw until the pipeline compiled and the target is on the GPU
+        let Some(pipeline) = pipeline_cache.get(post_process_pipeline.pipeline) else {
+            return Ok(());
+        };
+        let Some(bind_group) = bind_group.value.as_ref() else {
+            return Ok(());
+        };
+        let Some(swap_chain_texture) = windows
+            .windows
+            .get(&WindowId::primary())
+            .and_then(|window| window.swap_chain_texture.as_ref())
+        else {
+            return Ok(());
+        };
+
+        let mut render_pass =
+            render_context
+                .command_encoder
+                .begin_render_pass(&RenderPassDescriptor {
+                    label: Some("post_process_pass"),
+                    color_attachments: &[RenderPassColorAttachment {
+                        view: swap_chain_texture,
+                        resolve_target: None,
+                        // The fullscreen triangle overwrites every pixel
+                        ops: Operations {
+                            load: LoadOp::Load,
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+fn target_size(windows: &Windows) -> Extent3d {
+    let (width, height) = windows.get_primary().map_or((1, 1), |window| {
+        (window.physical_width(), window.physical_height())
+    });
+    Extent3d {
+        width: width.max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+    }
+}
+
+fn setup_target(mut commands: Commands, windows: Res<Windows>, mut images: ResMut<Assets<Image>>) {
+    let size = target_size(&windows);
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("post_process_target"),
+            size,
+            dimension: TextureDimension::D2,
+            // Main pass pipelines are specialized for this format only
+            format: TextureFormat::bevy_default(),
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..Default::default()
+    };
+    image.resize(size);
+
+    commands.insert_resource(PostProcessTarget {
+        image: images.add(image),
+    });
+}
+
+/// Redirects main cameras spawned to the primary window, a main camera
+/// already rendering elsewhere is left alone
+fn render_main_camera_to_target(
+    target: Res<PostProcessTarget>,
+    mut q_camera: Query<&mut Camera, Added<MainCameraMarker>>,
+) {
+    for mut camera in q_camera.iter_mut() {
+        if matches!(camera.target, RenderTarget::Window(id) if id == WindowId::primary()) {
+            camera.target = RenderTarget::Image(target.image.clone());
+        }
+    }
+}
+
+fn resize_target(
+    mut resized: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    target: Res<PostProcessTarget>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !resized.iter().any(|event| event.id == WindowId::primary()) {
+        return;
+    }
+    let size = target_size(&windows);
+    if let Some(image) = images.get_mut(&target.image) {
+        if image.texture_descriptor.size != size {
+            image.resize(size);
+        }
+    }
+}
+
+/// Vignette follows the speed level, motion blur the actual speed so it
+/// builds up while accelerating
+fn update_post_process_settings(
+    time: Res<Time>,
+    local_settings: Res<LocalSettingsLoader>,
+    descriptor: Res<PlayerShipDescriptor>,
+    q_ship: Query<&RigidBodyVelocityComponent, With<PlayerShipMarker>>,
+    mut settings: ResMut<PostProcessSettings>,
+) {
+    let graphics = local_settings.graphics();
+
+    let vignette = if graphics.vignette {
+        (VIGNETTE_BASE + VIGNETTE_PER_LEVEL * descriptor.speed_level as f32).min(VIGNETTE_MAX)
+    } else {
+        0.0
+    };
+
+    let motion_blur = match q_ship.get_single() {
+        Ok(velocity) if graphics.motion_blur => {
+            let slowest = TERMINAL_VELOCITY_Z[0];
+            let fastest = TERMINAL_VELOCITY_Z[TERMINAL_VELOCITY_Z.len() - 1];
+            let speed = ((velocity.linvel.z - slowest) / (fastest - slowest)).clamp(0.0, 1.0);
+            speed * MOTION_BLUR_MAX
+        }
+        _ => 0.0,
+    };
+
+    let t = 1.0 - (-EFFECT_RESPONSE * time.delta_seconds()).exp();
+    settings.vignette += (vignette - settings.vignette) * t;
+    settings.motion_blur += (motion_blur - settings.motion_blur) * t;
+}
+
+fn extract_post_process(
+    mut commands: Commands,
+    target: Res<PostProcessTarget>,
+    settings: Res<PostProcessSettings>,
+) {
+    commands.insert_resource(ExtractedPostProcess {
+        image: target.image.clone(),
+        settings: settings.clone(),
+    });
+}
+
+fn queue_post_process_bind_group(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    pipeline: Res<PostProcessPipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    post_process: Res<ExtractedPostProcess>,
+    mut bind_group: ResMut<PostProcessBindGroup>,
+) {
+    let PostProcessBindGroup { value, uniform } = bind_group.as_mut();
+    let Some(image) = gpu_images.get(&post_process.image) else {
+        *value = None;
+        return;
+    };
+    uniform.clear();
+    uniform.push(PostProcessUniform {
+        vignette: post_process.settings.vignette,
+        motion_blur: post_process.settings.motion_blur,
+    });
+    uniform.write_buffer(&render_device, &render_queue);
+    let Some(binding) = uniform.binding() else {
+        *value = None;
+        return;
+    };
+
+    *value = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("post_process_bind_group"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&image.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: binding,
+            },
+        ],
+    }));
+}
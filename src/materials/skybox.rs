@@ -1,100 +1,236 @@
-use bevy::core_pipeline::Transparent3d;
-use bevy::ecs::system::lifetimeless::{Read, SQuery};
+use bevy::asset::LoadState;
+use bevy::ecs::system::lifetimeless::{Read, SQuery, SRes};
 use bevy::ecs::system::SystemParamItem;
-use bevy::pbr::{
-    DrawMesh, MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup,
-};
 use bevy::prelude::{
-    shape, App, AssetServer, Assets, Commands, Component, ComputedVisibility, Entity, FromWorld,
-    GlobalTransform, Handle, Image, Mesh, Msaa, Plugin, Query, Res, ResMut, Shader, Visibility,
-    With, World,
-};
-use bevy::render::render_resource::{
-    SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+    error, shape, Added, AmbientLight, App, AssetEvent, AssetServer, Assets, Color, Commands,
+    Component, ComputedVisibility, Entity, EulerRot, EventReader, EventWriter, GlobalTransform,
+    Handle, Image, Mat4, Mesh, Plugin, Quat, Query, Res, ResMut, Vec3, Vec4, Visibility,
 };
-
-use bevy::render::mesh::MeshVertexBufferLayout;
 use bevy::render::render_asset::RenderAssets;
-use bevy::render::render_phase::{
-    AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
-    SetItemPipeline, TrackedRenderPass,
-};
+use bevy::render::render_resource::std140::AsStd140;
 use bevy::render::render_resource::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, CompareFunction, PrimitiveTopology,
-    RenderPipelineCache, RenderPipelineDescriptor, SamplerBindingType, ShaderStages,
-    TextureSampleType, TextureViewDimension,
+    BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferSize,
+    CompareFunction, RenderPipelineDescriptor, SamplerBindingType, ShaderStages, TextureSampleType,
+    TextureViewDimension,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::NoFrustumCulling;
+use bevy::utils::HashMap;
+use bevy_easings::{custom_ease_system, Lerp};
+
+use crate::materials::cubemap::{is_cubemap, CubemapLayout};
+use crate::materials::custom::{
+    BoundResource, CustomMaterial, CustomMaterialPlugin, CustomPipeline, EntityUniforms,
+    MaterialBinding, ViewMaterialMesh,
 };
-use bevy::render::renderer::RenderDevice;
-use bevy::render::view::{ExtractedView, NoFrustumCulling};
-use bevy::render::{RenderApp, RenderStage};
+use crate::materials::exr_loader::ExrLoader;
+use crate::materials::irradiance::SkyIrradiance;
+use crate::MainCameraMarker;
 
-pub struct SkyboxPlugin;
+pub struct SkyboxPlugin {
+    /// Asset path of the sky texture
+    pub texture: String,
+    /// Asset path of the texture `DayNightPlugin` fades to at night, in the
+    /// same layout as `texture`
+    pub night_texture: Option<String>,
+    pub layout: CubemapLayout,
+    /// Replace `AmbientLight` with the sky's irradiance once it's converted
+    pub ambient_from_sky: bool,
+    /// Procedural sky drawn instead of `texture` when set, it has no
+    /// irradiance so `ambient_from_sky` is ignored
+    pub atmosphere: Option<Atmosphere>,
+}
 
+pub struct SkyboxSettings {
+    pub texture: String,
+    pub night_texture: Option<String>,
+    pub layout: CubemapLayout,
+    pub ambient_from_sky: bool,
+    pub atmosphere: Option<Atmosphere>,
+}
+
+/// Sky drawn behind everything a camera sees, cameras without one draw no sky
 #[derive(Component, Clone)]
-pub struct SkyboxMaterial {
-    pub texture: Handle<Image>,
+pub enum SkyboxMaterial {
+    /// 6-layer arrays, see `SkyboxTextureConversionQueue`. `blend` goes from
+    /// 0 showing `day` to 1 showing `night`
+    Texture {
+        day: Handle<Image>,
+        night: Handle<Image>,
+        blend: f32,
+    },
+    /// Rayleigh and Mie scattering computed in the shader
+    Atmosphere(Atmosphere),
 }
 
-#[derive(Default)]
-pub struct SkyboxTextureConversionQueue {
-    queue: Vec<Handle<Image>>,
+/// Adjustments on top of the `SkyboxMaterial` of the same camera, eased like
+/// `Transform` once `custom_ease_system::<SkyboxParams>` runs
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SkyboxParams {
+    /// Yaw, pitch and roll in radians. Ignored by the atmosphere, whose sun
+    /// and moon set its orientation
+    pub rotation: Vec3,
+    /// Multiplies the sky color
+    pub tint: Color,
+    pub exposure: f32,
 }
 
-pub type SkyboxDrawCustom = (
-    SetItemPipeline,
-    SetMeshViewBindGroup<0>,
-    SetMeshBindGroup<1>,
-    SetMaterialBindGroup<2>,
-    DrawMesh,
-);
-
-pub struct SkyboxPipeline {
-    shader: Handle<Shader>,
-    pub mesh_pipeline: MeshPipeline,
-    pub material_bind_group_layout: BindGroupLayout,
+/// Sky lit by a sun and a moon, stars fade in once the sun is below the
+/// horizon. Directions point from the ground towards the light
+#[derive(Clone, Copy, Debug)]
+pub struct Atmosphere {
+    pub sun_direction: Vec3,
+    pub sun_intensity: f32,
+    pub moon_direction: Vec3,
+    pub star_brightness: f32,
 }
 
-pub struct SetMaterialBindGroup<const I: usize>;
+#[derive(AsStd140)]
+struct AtmosphereUniform {
+    sun_direction: Vec3,
+    sun_intensity: f32,
+    moon_direction: Vec3,
+    star_brightness: f32,
+    tint: Vec4,
+    exposure: f32,
+}
+
+#[derive(AsStd140)]
+struct SkyboxTextureUniform {
+    /// Inverse of the sky rotation, applied to view rays
+    rotation: Mat4,
+    tint: Vec4,
+    exposure: f32,
+    blend: f32,
+}
 
-#[derive(Clone, Component)]
-pub struct MaterialBindGroup {
-    pub value: BindGroup,
+/// Material given to the main camera, built from `SkyboxSettings`
+pub struct DefaultSkybox {
+    pub material: SkyboxMaterial,
+}
+
+/// Sent once a queued texture is a 6-layer array the skybox can sample
+pub struct SkyboxTextureReady {
+    pub handle: Handle<Image>,
+}
+
+#[derive(Default)]
+pub struct SkyboxTextureConversionQueue {
+    queue: Vec<(Handle<Image>, CubemapLayout)>,
+    /// Layout of every converted texture, to convert it again when it's
+    /// hot-reloaded as a single layer image
+    converted: HashMap<Handle<Image>, CubemapLayout>,
 }
 
 impl Plugin for SkyboxPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<SkyboxTextureConversionQueue>()
-            .add_startup_system(setup)
-            .add_system(process_skybox_texture_conversion_queue);
-
-        let render_app = app.sub_app_mut(RenderApp);
-        render_app
-            .add_render_command::<Transparent3d, SkyboxDrawCustom>()
-            .init_resource::<SkyboxPipeline>()
-            .init_resource::<SpecializedMeshPipelines<SkyboxPipeline>>()
-            .add_system_to_stage(RenderStage::Extract, extract_skybox_material)
-            .add_system_to_stage(RenderStage::Queue, queue_skybox_pipeline)
-            .add_system_to_stage(RenderStage::Queue, queue_view_extra_bind_group);
+        app.insert_resource(SkyboxSettings {
+            texture: self.texture.clone(),
+            night_texture: self.night_texture.clone(),
+            layout: self.layout,
+            ambient_from_sky: self.ambient_from_sky,
+            atmosphere: self.atmosphere,
+        })
+        .init_asset_loader::<ExrLoader>()
+        .init_resource::<SkyboxTextureConversionQueue>()
+        .add_event::<SkyboxTextureReady>()
+        .add_startup_system(setup)
+        .add_system(attach_default_skybox)
+        .add_system(process_skybox_texture_conversion_queue)
+        .add_system(custom_ease_system::<SkyboxParams>);
+
+        app.add_plugin(CustomMaterialPlugin::<SkyboxMaterial>::default());
+    }
+}
+
+impl Default for SkyboxPlugin {
+    fn default() -> Self {
+        SkyboxPlugin {
+            texture: "textures/sky.png".to_string(),
+            night_texture: None,
+            layout: CubemapLayout::VerticalStrip,
+            ambient_from_sky: true,
+            atmosphere: None,
+        }
+    }
+}
+
+impl SkyboxParams {
+    pub fn rotation_quat(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::YXZ,
+            self.rotation.x,
+            self.rotation.y,
+            self.rotation.z,
+        )
+    }
+}
+
+impl Default for SkyboxParams {
+    fn default() -> Self {
+        SkyboxParams {
+            rotation: Vec3::ZERO,
+            tint: Color::WHITE,
+            exposure: 1.0,
+        }
+    }
+}
+
+impl Lerp for SkyboxParams {
+    type Scalar = f32;
+
+    fn lerp(&self, other: &Self, scalar: &Self::Scalar) -> Self {
+        let tint = Vec4::from(self.tint.as_linear_rgba_f32())
+            .lerp(Vec4::from(other.tint.as_linear_rgba_f32()), *scalar);
+        SkyboxParams {
+            rotation: self.rotation.lerp(other.rotation, *scalar),
+            tint: Color::rgba_linear(tint.x, tint.y, tint.z, tint.w),
+            exposure: self.exposure + (other.exposure - self.exposure) * scalar,
+        }
+    }
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        let sun_direction = Vec3::new(0.3, 0.4, -1.0).normalize();
+        Atmosphere {
+            sun_direction,
+            sun_intensity: 22.0,
+            moon_direction: -sun_direction,
+            star_brightness: 1.0,
+        }
     }
 }
 
 impl SkyboxTextureConversionQueue {
-    pub fn add(&mut self, handle: Handle<Image>) {
-        self.queue.push(handle);
+    /// Queue `handle` unless it already is. Images that are already 6-layer
+    /// arrays are left as they are, so re-adding a converted texture only
+    /// sends `SkyboxTextureReady` again
+    pub fn add(&mut self, handle: Handle<Image>, layout: CubemapLayout) {
+        if !self.queue.iter().any(|(queued, _)| *queued == handle) {
+            self.queue.push((handle, layout));
+        }
     }
 }
 
-impl FromWorld for SkyboxPipeline {
-    fn from_world(world: &mut World) -> Self {
-        let world = world.cell();
-        let asset_server = world.get_resource::<AssetServer>().unwrap();
-        let shader = asset_server.load("shaders/skybox.wgsl");
+impl CustomMaterial for SkyboxMaterial {
+    /// Whether the atmosphere is drawn instead of textures
+    type Key = bool;
+    type Param = (SRes<RenderAssets<Image>>, SQuery<Read<SkyboxParams>>);
+    type Uniforms = (
+        EntityUniforms<SkyboxTextureUniform>,
+        EntityUniforms<AtmosphereUniform>,
+    );
+    type Companion = SkyboxParams;
 
-        let render_device = world.get_resource_mut::<RenderDevice>().unwrap();
-        let extra_bind_group_layout =
+    const SHADER: &'static str = "shaders/skybox.wgsl";
+    const BINDING: MaterialBinding = MaterialBinding::View;
+
+    fn bind_group_layouts(render_device: &RenderDevice) -> Vec<BindGroupLayout> {
+        let texture_bind_group_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("view extra uniforms bind group"),
+                label: Some("skybox texture bind group"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
@@ -109,191 +245,313 @@ impl FromWorld for SkyboxPipeline {
                     BindGroupLayoutEntry {
                         binding: 1,
                         visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Sampler(SamplerBindingType::Filtering),
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(
+                                SkyboxTextureUniform::std140_size_static() as u64,
+                            ),
+                        },
+                        count: None,
+                    },
                 ],
             });
+        let atmosphere_bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("atmosphere bind group"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(
+                            AtmosphereUniform::std140_size_static() as u64
+                        ),
+                    },
+                    count: None,
+                }],
+            });
+
+        vec![texture_bind_group_layout, atmosphere_bind_group_layout]
+    }
 
-        let mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap();
+    fn key(&self) -> bool {
+        matches!(self, SkyboxMaterial::Atmosphere(_))
+    }
+
+    fn layout_index(atmosphere: bool) -> usize {
+        atmosphere as usize
+    }
 
-        SkyboxPipeline {
-            shader,
-            mesh_pipeline: mesh_pipeline.clone(),
-            material_bind_group_layout: extra_bind_group_layout,
+    fn specialize(atmosphere: bool, descriptor: &mut RenderPipelineDescriptor) {
+        if atmosphere {
+            descriptor.vertex.shader_defs.push("ATMOSPHERE".to_string());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("ATMOSPHERE".to_string());
+            }
+        }
+        // Drawn at the far plane where no opaque geometry wrote depth, without
+        // writing any itself
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.depth_compare = CompareFunction::GreaterEqual;
+            depth_stencil.depth_write_enabled = false;
         }
     }
-}
 
-impl SpecializedMeshPipeline for SkyboxPipeline {
-    type Key = MeshPipelineKey;
+    /// Opaques are sorted front to back, so the sky comes after all of them
+    /// and before the transparent phase
+    fn sort_distance(_distance: f32) -> f32 {
+        f32::MAX
+    }
 
-    fn specialize(
+    fn prepare(
         &self,
-        key: Self::Key,
-        layout: &MeshVertexBufferLayout,
-    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
-        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
-        descriptor.vertex.shader = self.shader.clone();
-        descriptor.fragment = descriptor.fragment.map(|mut fragment| {
-            fragment.shader = self.shader.clone();
-            fragment
-        });
-        descriptor.layout = Some(vec![
-            self.mesh_pipeline.view_layout.clone(),
-            self.mesh_pipeline.mesh_layout.clone(),
-            self.material_bind_group_layout.clone(),
-        ]);
-        descriptor.depth_stencil = descriptor.depth_stencil.map(|mut depth_stencil| {
-            depth_stencil.depth_compare = CompareFunction::GreaterEqual;
-            depth_stencil
-        });
-        Ok(descriptor)
+        entity: Entity,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        (textures, atmospheres): &mut Self::Uniforms,
+        (gpu_images, q_params): &mut SystemParamItem<Self::Param>,
+    ) -> Option<Vec<BoundResource>> {
+        let params = q_params.get(entity).copied().unwrap_or_default();
+        let tint = Vec4::from(params.tint.as_linear_rgba_f32());
+
+        match self {
+            SkyboxMaterial::Texture { day, night, blend } => {
+                let day = gpu_images.get(day)?;
+                let night = gpu_images.get(night)?;
+                let uniform = textures.write(
+                    entity,
+                    SkyboxTextureUniform {
+                        rotation: Mat4::from_quat(params.rotation_quat().inverse()),
+                        tint,
+                        exposure: params.exposure,
+                        blend: blend.clamp(0.0, 1.0),
+                    },
+                    render_device,
+                    render_queue,
+                )?;
+                Some(vec![
+                    (&day.texture_view).into(),
+                    (&night.texture_view).into(),
+                    (&day.sampler).into(),
+                    uniform,
+                ])
+            }
+            SkyboxMaterial::Atmosphere(atmosphere) => {
+                let uniform = atmospheres.write(
+                    entity,
+                    AtmosphereUniform {
+                        sun_direction: atmosphere.sun_direction.normalize_or_zero(),
+                        sun_intensity: atmosphere.sun_intensity,
+                        moon_direction: atmosphere.moon_direction.normalize_or_zero(),
+                        star_brightness: atmosphere.star_brightness,
+                        tint,
+                        exposure: params.exposure,
+                    },
+                    render_device,
+                    render_queue,
+                )?;
+                Some(vec![uniform])
+            }
+        }
     }
-}
 
-impl<const I: usize> EntityRenderCommand for SetMaterialBindGroup<I> {
-    type Param = SQuery<Read<MaterialBindGroup>>;
-
-    fn render<'w>(
-        view: Entity,
-        _item: Entity,
-        query: SystemParamItem<'w, '_, Self::Param>,
-        pass: &mut TrackedRenderPass<'w>,
-    ) -> RenderCommandResult {
-        let Ok(bind_group) = query.get(view) else { return RenderCommandResult::Failure };
-        pass.set_bind_group(I, &bind_group.value, &[]);
-        RenderCommandResult::Success
+    fn bind_group(
+        &self,
+        entity: Entity,
+        render_device: &RenderDevice,
+        pipeline: &CustomPipeline<Self>,
+        (textures, atmospheres): &Self::Uniforms,
+        (gpu_images, _): &mut SystemParamItem<Self::Param>,
+    ) -> Option<BindGroup> {
+        let bind_group = match self {
+            SkyboxMaterial::Texture { day, night, .. } => {
+                let day = gpu_images.get(day)?;
+                let night = gpu_images.get(night)?;
+                let uniform = textures.binding(entity)?;
+
+                render_device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("skybox_texture_bind_group"),
+                    layout: &pipeline.bind_group_layouts[0],
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(&day.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&night.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::Sampler(&day.sampler),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: uniform,
+                        },
+                    ],
+                })
+            }
+            SkyboxMaterial::Atmosphere(_) => {
+                let uniform = atmospheres.binding(entity)?;
+
+                render_device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("atmosphere_bind_group"),
+                    layout: &pipeline.bind_group_layouts[1],
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: uniform,
+                    }],
+                })
+            }
+        };
+        Some(bind_group)
     }
 }
 
 pub fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    settings: Res<SkyboxSettings>,
     mut conversion_queue: ResMut<SkyboxTextureConversionQueue>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    let skybox_texture = asset_server.load("textures/sky.png");
-    conversion_queue.add(skybox_texture.clone());
+    let material = match settings.atmosphere {
+        Some(atmosphere) => SkyboxMaterial::Atmosphere(atmosphere),
+        None => {
+            let day: Handle<Image> = asset_server.load(settings.texture.as_str());
+            conversion_queue.add(day.clone(), settings.layout);
+            // Without a night texture the day one is shown all along
+            let night = match &settings.night_texture {
+                Some(path) => {
+                    let night = asset_server.load(path.as_str());
+                    conversion_queue.add(night.clone(), settings.layout);
+                    night
+                }
+                None => day.clone(),
+            };
+            SkyboxMaterial::Texture {
+                day,
+                night,
+                blend: 0.0,
+            }
+        }
+    };
+
+    commands.insert_resource(DefaultSkybox { material });
 
     commands.spawn().insert_bundle((
         meshes.add(Mesh::from(shape::Cube { size: 2.0 })),
         GlobalTransform::default(),
-        SkyboxMaterial {
-            texture: skybox_texture,
-        },
+        ViewMaterialMesh::<SkyboxMaterial>::default(),
         Visibility::default(),
         ComputedVisibility::default(),
         NoFrustumCulling,
     ));
 }
 
-fn process_skybox_texture_conversion_queue(
-    mut conversion_queue: ResMut<SkyboxTextureConversionQueue>,
-    mut images: ResMut<Assets<Image>>,
+fn attach_default_skybox(
+    mut commands: Commands,
+    default_skybox: Res<DefaultSkybox>,
+    q_camera: Query<Entity, Added<MainCameraMarker>>,
 ) {
-    let mut i = 0;
-    loop {
-        match conversion_queue.queue.get(i) {
-            None => break,
-            Some(item) => match images.get_mut(item) {
-                None => {
-                    i += 1;
-                }
-                Some(image) => {
-                    conversion_queue.queue.remove(i);
-                    image.reinterpret_stacked_2d_as_array(6);
-                }
-            },
-        }
+    for entity in q_camera.iter() {
+        commands
+            .entity(entity)
+            .insert_bundle((default_skybox.material.clone(), SkyboxParams::default()));
     }
 }
 
-fn queue_view_extra_bind_group(
+#[allow(clippy::too_many_arguments)]
+fn process_skybox_texture_conversion_queue(
     mut commands: Commands,
-    render_device: Res<RenderDevice>,
-    skybox_pipeline: Res<SkyboxPipeline>,
-    views: Query<Entity, With<ExtractedView>>,
-    gpu_images: Res<RenderAssets<Image>>,
-    skybox: Query<(Entity, &SkyboxMaterial)>,
+    settings: Res<SkyboxSettings>,
+    asset_server: Res<AssetServer>,
+    mut conversion_queue: ResMut<SkyboxTextureConversionQueue>,
+    mut images: ResMut<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut ready: EventWriter<SkyboxTextureReady>,
+    default_skybox: Res<DefaultSkybox>,
+    skybox: Query<&SkyboxMaterial>,
 ) {
-    let (_, skybox) = skybox.get_single().unwrap();
-
-    let Some((texture_view, sampler)) = skybox_pipeline
-        .mesh_pipeline
-        .get_image_texture(gpu_images.as_ref(), &Some(skybox.texture.clone()))
-        else { return };
-
-    let group = MaterialBindGroup {
-        value: render_device.create_bind_group(&BindGroupDescriptor {
-            label: Some("view_extra_bind_group"),
-            layout: &skybox_pipeline.material_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(texture_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(sampler),
-                },
-            ],
-        }),
-    };
-    for entity in views.iter() {
-        commands.entity(entity).insert(group.clone());
+    // Our own conversions modify the images too, only reloaded ones are
+    // single layer again
+    for event in image_events.iter() {
+        let AssetEvent::Modified { handle } = event else {
+            continue;
+        };
+        let Some(&layout) = conversion_queue.converted.get(handle) else {
+            continue;
+        };
+        if images.get(handle).map_or(false, |image| !is_cubemap(image)) {
+            conversion_queue.add(handle.clone(), layout);
+        }
     }
-}
 
-fn extract_skybox_material(mut commands: Commands, query: Query<(Entity, &SkyboxMaterial)>) {
-    let (entity, material) = query.get_single().unwrap();
-    commands.get_or_spawn(entity).insert(SkyboxMaterial {
-        texture: material.texture.clone(),
-    });
-}
+    for (handle, layout) in std::mem::take(&mut conversion_queue.queue) {
+        let Some(image) = images.get(&handle) else {
+            if asset_server.get_load_state(&handle) == LoadState::Failed {
+                error!("Couldn't load skybox texture, dropping it from the conversion queue");
+            } else {
+                conversion_queue.queue.push((handle, layout));
+            }
+            continue;
+        };
 
-#[allow(clippy::type_complexity)]
-#[allow(clippy::too_many_arguments)]
-fn queue_skybox_pipeline(
-    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
-    custom_pipeline: Res<SkyboxPipeline>,
-    msaa: Res<Msaa>,
-    render_meshes: Res<RenderAssets<Mesh>>,
-    mut pipelines: ResMut<SpecializedMeshPipelines<SkyboxPipeline>>,
-    mut pipeline_cache: ResMut<RenderPipelineCache>,
-    material_meshes: Query<
-        (Entity, &Handle<Mesh>, &MeshUniform),
-        (With<Handle<Mesh>>, With<SkyboxMaterial>),
-    >,
-    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
-) {
-    let draw_function = transparent_3d_draw_functions
-        .read()
-        .get_id::<SkyboxDrawCustom>()
-        .unwrap();
-    let key = MeshPipelineKey::from_msaa_samples(msaa.samples)
-        | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList);
-
-    for (view, mut transparent_phase) in views.iter_mut() {
-        let view_matrix = view.transform.compute_matrix();
-        let view_row_2 = view_matrix.row(2);
-        for (entity, mesh_handle, mesh) in material_meshes.iter() {
-            let render_mesh = render_meshes.get(mesh_handle).unwrap();
-            let pipeline = pipelines
-                .specialize(
-                    &mut pipeline_cache,
-                    &custom_pipeline,
-                    key,
-                    &render_mesh.layout,
-                )
-                .unwrap();
-
-            transparent_phase.add(Transparent3d {
-                entity,
-                pipeline,
-                draw_function,
-                distance: view_row_2.dot(mesh.transform.col(3)),
-            });
+        // Only borrow mutably when converting, so converted images don't look modified
+        if !is_cubemap(image) {
+            let image = images.get_mut(&handle).unwrap();
+            if let Err(err) = layout.convert(image) {
+                error!("Couldn't convert skybox texture from {:?}: {}", layout, err);
+                continue;
+            }
         }
+        let image = images.get(&handle).unwrap();
+
+        // The night sky only dims the day irradiance, see `DayNightPlugin`
+        let mut materials = skybox
+            .iter()
+            .chain(std::iter::once(&default_skybox.material));
+        let is_day = materials.any(
+            |material| matches!(material, SkyboxMaterial::Texture { day, .. } if *day == handle),
+        );
+        // The fog follows the horizon of the sky even when the ambient doesn't
+        if is_day {
+            match SkyIrradiance::from_cubemap(image) {
+                Ok(irradiance) => {
+                    if settings.ambient_from_sky {
+                        let (color, brightness) = irradiance.ambient();
+                        ambient_light.color = color;
+                        ambient_light.brightness = brightness;
+                    }
+                    commands.insert_resource(irradiance);
+                }
+                Err(err) => error!("Couldn't compute the skybox irradiance: {}", err),
+            }
+        }
+
+        conversion_queue
+            .converted
+            .insert(handle.clone_weak(), layout);
+        ready.send(SkyboxTextureReady { handle });
     }
 }
@@ -1,24 +1,88 @@
 use bevy::prelude::*;
+use bevy_easings::{Ease, EaseFunction, EasingComponent, EasingType};
+use std::time::Duration;
 
 pub struct AlterTransformOncePlugin;
 
 #[derive(Component)]
 pub struct AlterTransformOnce {
     pub target: Transform,
+    /// Snap to `target` on the first frame when `None`
+    pub tween: Option<TransformTween>,
+}
+
+#[derive(Clone, Copy)]
+pub struct TransformTween {
+    pub from: TweenStart,
+    pub duration: Duration,
+    pub ease: EaseFunction,
+}
+
+#[derive(Clone, Copy)]
+pub enum TweenStart {
+    /// Transform the entity has when the tween starts
+    Current,
+    /// Target moved by this translation
+    Offset(Vec3),
+}
+
+/// Transform currently eased by `bevy_easings`
+#[derive(Component)]
+pub struct TweeningTransform;
+
+/// Sent once `entity` has reached the target of its `AlterTransformOnce`
+pub struct AlterTransformFinished {
+    pub entity: Entity,
 }
 
 impl Plugin for AlterTransformOncePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(init_translation);
+        app.add_event::<AlterTransformFinished>()
+            .add_system(init_translation)
+            .add_system(finish_tweens);
     }
 }
 
 pub fn init_translation(
     mut commands: Commands,
+    mut finished: EventWriter<AlterTransformFinished>,
     mut q: Query<(Entity, &mut Transform, &AlterTransformOnce)>,
 ) {
     for (entity, mut transform, rm) in q.iter_mut() {
-        *transform = rm.target;
-        commands.entity(entity).remove::<AlterTransformOnce>();
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.remove::<AlterTransformOnce>();
+
+        let Some(tween) = rm.tween else {
+            *transform = rm.target;
+            finished.send(AlterTransformFinished { entity });
+            continue;
+        };
+
+        if let TweenStart::Offset(offset) = tween.from {
+            *transform = rm.target;
+            transform.translation += offset;
+        }
+        entity_commands.insert_bundle((
+            (*transform).ease_to(
+                rm.target,
+                tween.ease,
+                EasingType::Once {
+                    duration: tween.duration,
+                },
+            ),
+            TweeningTransform,
+        ));
+    }
+}
+
+/// `bevy_easings` removes the easing once it's done
+pub fn finish_tweens(
+    mut commands: Commands,
+    mut finished: EventWriter<AlterTransformFinished>,
+    q: Query<Entity, (With<TweeningTransform>, Without<EasingComponent<Transform>>)>,
+) {
+    for entity in q.iter() {
+        commands.entity(entity).remove::<TweeningTransform>();
+        finished.send(AlterTransformFinished { entity });
     }
 }
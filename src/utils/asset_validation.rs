@@ -0,0 +1,133 @@
+//! Offline checks of `assets/`, catches broken models and manifests before
+//! they fail silently in game
+
+use crate::utils::manifest::{Manifest, MANIFEST_EXTENSION};
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Scene every model is spawned from, see `spawn_model`
+const SCENE_INDEX: usize = 0;
+
+#[derive(Clone, Debug)]
+pub struct AssetProblem {
+    /// Path relative to the assets directory
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for AssetProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+/// Check every `gltf` model under `root` and the manifest next to it
+pub fn validate_assets(root: &Path) -> Vec<AssetProblem> {
+    let mut problems = Vec::new();
+
+    for path in gltf_files(root, Path::new("")) {
+        validate_gltf(root, &path, &mut problems);
+
+        let manifest_path = path.with_extension(MANIFEST_EXTENSION);
+        if root.join(&manifest_path).exists() {
+            validate_manifest(root, &manifest_path, &mut problems);
+        }
+    }
+
+    problems
+}
+
+/// Every `.gltf` file under `root.join(dir)`, relative to `root`
+fn gltf_files(root: &Path, dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root.join(dir)) else {
+        return Vec::new();
+    };
+
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let path = dir.join(entry.file_name());
+        if entry.file_type().map_or(false, |t| t.is_dir()) {
+            files.extend(gltf_files(root, &path));
+        } else if path.extension().map_or(false, |ext| ext == "gltf") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+fn validate_manifest(root: &Path, path: &Path, problems: &mut Vec<AssetProblem>) {
+    let mut problem = |message: String| {
+        problems.push(AssetProblem {
+            path: path.to_path_buf(),
+            message,
+        })
+    };
+
+    let manifest = match Manifest::from_asset_path(root, path) {
+        Ok(manifest) => manifest,
+        Err(err) => return problem(err.to_string()),
+    };
+
+    let scale = manifest.transform.scale;
+    for (axis, value) in [("x", scale.x), ("y", scale.y), ("z", scale.z)] {
+        if value == 0.0 {
+            problem(format!(
+                "scale.{} is zero, the model would be invisible",
+                axis
+            ));
+        }
+    }
+
+    for level in manifest.lod.iter().flat_map(|lod| lod.levels.iter()) {
+        if !root.join(&level.model).exists() {
+            problem(format!(
+                "LOD level references missing model `{}`",
+                level.model
+            ));
+        }
+    }
+}
+
+fn validate_gltf(root: &Path, path: &Path, problems: &mut Vec<AssetProblem>) {
+    let mut problem = |message: String| {
+        problems.push(AssetProblem {
+            path: path.to_path_buf(),
+            message,
+        })
+    };
+
+    let gltf: Value = match fs::read(root.join(path))
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|err| err.to_string()))
+    {
+        Ok(gltf) => gltf,
+        Err(err) => return problem(format!("couldn't read glTF: {}", err)),
+    };
+
+    let scenes = gltf["scenes"].as_array().map_or(0, Vec::len);
+    if scenes <= SCENE_INDEX {
+        problem(format!("missing label `#Scene{}`", SCENE_INDEX));
+    }
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for key in ["buffers", "images"] {
+        let Some(entries) = gltf[key].as_array() else {
+            continue;
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            // Embedded data and images stored in buffer views have nothing to check
+            let Some(uri) = entry["uri"].as_str() else {
+                continue;
+            };
+            if uri.starts_with("data:") {
+                continue;
+            }
+            if !root.join(dir).join(uri).exists() {
+                problem(format!("{}[{}] references missing file `{}`", key, i, uri));
+            }
+        }
+    }
+}
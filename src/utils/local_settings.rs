@@ -7,6 +7,9 @@ use std::fs;
 use std::fs::File;
 use std::io::Write;
 
+/// Directory under the user config dir, the game's binary name
+const CONFIG_DIR_NAME: &str = "race-the-moon";
+
 pub struct LocalSettingsPlugin {
     pub filename: String,
 }
@@ -25,11 +28,32 @@ pub enum Action {
 
     Cancel,
     Confirm,
+
+    ToggleEditor,
+    EditorNextModel,
+    EditorPlace,
+    EditorTranslate,
+    EditorScale,
+    EditorDelete,
+    EditorSave,
+    EditorForward,
+    EditorBackward,
+    EditorUp,
+    EditorDown,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct LocalSettings {
     keybindings: HashMap<Action, KeyCode>,
+    /// Missing from settings files written by older versions
+    #[serde(default)]
+    graphics: GraphicsSettings,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GraphicsSettings {
+    pub vignette: bool,
+    pub motion_blur: bool,
 }
 
 impl Plugin for LocalSettingsPlugin {
@@ -46,13 +70,18 @@ impl LocalSettingsLoader {
     fn init(&mut self) {
         let file_path = dirs::config_dir()
             .unwrap()
-            .join(env!("CARGO_BIN_NAME"))
+            .join(CONFIG_DIR_NAME)
             .join(&self.filename);
         match fs::try_exists(&file_path) {
             Ok(true) => {
                 let f = File::open(file_path).unwrap();
 
                 self.loaded = serde_json::from_reader(f).unwrap();
+
+                // Settings files written by older versions don't know about newer actions
+                for (action, key) in LocalSettings::default().keybindings {
+                    self.loaded.keybindings.entry(action).or_insert(key);
+                }
             }
             Ok(false) => {
                 fs::create_dir_all(file_path.parent().unwrap()).unwrap();
@@ -72,6 +101,10 @@ impl LocalSettingsLoader {
     pub fn key(&self, action: impl AsRef<Action>) -> KeyCode {
         *self.loaded.keybindings.get(action.as_ref()).unwrap()
     }
+
+    pub fn graphics(&self) -> &GraphicsSettings {
+        &self.loaded.graphics
+    }
 }
 
 impl Default for LocalSettings {
@@ -83,7 +116,28 @@ impl Default for LocalSettings {
                 Action::Special => KeyCode::Space,
                 Action::Cancel => KeyCode::Escape,
                 Action::Confirm => KeyCode::Return,
+                Action::ToggleEditor => KeyCode::F1,
+                Action::EditorNextModel => KeyCode::Tab,
+                Action::EditorPlace => KeyCode::Insert,
+                Action::EditorTranslate => KeyCode::G,
+                Action::EditorScale => KeyCode::S,
+                Action::EditorDelete => KeyCode::Delete,
+                Action::EditorSave => KeyCode::F5,
+                Action::EditorForward => KeyCode::Up,
+                Action::EditorBackward => KeyCode::Down,
+                Action::EditorUp => KeyCode::PageUp,
+                Action::EditorDown => KeyCode::PageDown,
             },
+            graphics: GraphicsSettings::default(),
+        }
+    }
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            vignette: true,
+            motion_blur: true,
         }
     }
 }
@@ -0,0 +1,162 @@
+use crate::materials::instancing::InstancedModel;
+use crate::utils::manifest::ManifestLod;
+use crate::MainCameraMarker;
+use bevy::asset::AssetPath;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use std::path::Path;
+
+/// Levels of detail of a spawned model, level 0 is the model itself
+#[derive(Component)]
+pub struct Lod {
+    /// `gltf` of every level, used to switch instanced models
+    models: Vec<Handle<Gltf>>,
+    /// Asset path of every level above 0
+    level_paths: Vec<String>,
+    /// Distance from which each level is shown
+    distances: Vec<f32>,
+    max_distance: Option<f32>,
+    /// Level picked by the last `update_lods`, `None` until it first ran
+    shown: Option<Option<usize>>,
+}
+
+/// Root of the scene of a level above 0, child of the model entity
+#[derive(Component)]
+pub struct LodLevel {
+    index: usize,
+}
+
+impl Lod {
+    pub fn new(base: Handle<Gltf>, lod: &ManifestLod, asset_server: &AssetServer) -> Self {
+        let mut levels = lod.levels.clone();
+        levels.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+        Lod {
+            models: std::iter::once(base)
+                .chain(
+                    levels
+                        .iter()
+                        .map(|level| asset_server.load(level.model.as_str())),
+                )
+                .collect(),
+            level_paths: levels.iter().map(|level| level.model.clone()).collect(),
+            distances: std::iter::once(0.0)
+                .chain(levels.iter().map(|level| level.distance))
+                .collect(),
+            max_distance: lod.max_distance,
+            shown: None,
+        }
+    }
+
+    /// Level to show at `distance` from the camera, `None` when culled
+    pub fn level_at(&self, distance: f32) -> Option<usize> {
+        if self.max_distance.map_or(false, |max| distance > max) {
+            return None;
+        }
+        Some(
+            self.distances
+                .iter()
+                .rposition(|from| distance >= *from)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Spawn the scene of every level above 0 under `entity`, hidden until
+    /// `update_lods` picks them
+    pub fn spawn_levels(
+        &self,
+        commands: &mut Commands,
+        scene_spawner: &mut SceneSpawner,
+        asset_server: &AssetServer,
+        entity: Entity,
+    ) {
+        for (i, path) in self.level_paths.iter().enumerate() {
+            let root = commands
+                .spawn_bundle((
+                    Transform::default(),
+                    GlobalTransform::default(),
+                    LodLevel { index: i + 1 },
+                ))
+                .id();
+            commands.entity(entity).push_children(&[root]);
+            scene_spawner.spawn_as_child(
+                asset_server.load(AssetPath::new_ref(Path::new(path), Some("Scene0"))),
+                root,
+            );
+        }
+    }
+}
+
+/// Switch levels of detail by distance to the main camera. Instanced models
+/// swap their `gltf`, others show the scene of the current level only.
+/// Visibility isn't inherited, so scenes are walked when the level changes
+/// and when their nodes are spawned, a scene is spawned all at once under
+/// its root
+#[allow(clippy::type_complexity)]
+pub fn update_lods(
+    q_camera: Query<&GlobalTransform, With<MainCameraMarker>>,
+    mut q_lods: Query<(
+        Entity,
+        &GlobalTransform,
+        &mut Lod,
+        Option<&mut InstancedModel>,
+    )>,
+    q_children: Query<&Children>,
+    q_new_children: Query<(), Changed<Children>>,
+    q_levels: Query<&LodLevel>,
+    mut q_visibility: Query<&mut Visibility>,
+) {
+    let Ok(camera) = q_camera.get_single() else {
+        return;
+    };
+
+    for (entity, transform, mut lod, instanced) in q_lods.iter_mut() {
+        let level = lod.level_at(transform.translation.distance(camera.translation));
+        let switched = lod.shown != Some(level);
+        if switched {
+            lod.shown = Some(level);
+        }
+
+        if let Some(mut instanced) = instanced {
+            if !switched {
+                continue;
+            }
+            if let Some(level) = level {
+                if instanced.gltf != lod.models[level] {
+                    instanced.gltf = lod.models[level].clone();
+                }
+            }
+            set_visible(&mut q_visibility, entity, level.is_some());
+            continue;
+        }
+
+        let Ok(children) = q_children.get(entity) else {
+            continue;
+        };
+        let new_children = q_new_children.get(entity).is_ok();
+        for child in children.iter() {
+            if !switched && !new_children && q_new_children.get(*child).is_err() {
+                continue;
+            }
+            let child_level = q_levels.get(*child).map_or(0, |level| level.index);
+            let visible = level == Some(child_level);
+
+            let mut stack = vec![*child];
+            while let Some(node) = stack.pop() {
+                set_visible(&mut q_visibility, node, visible);
+                if let Ok(children) = q_children.get(node) {
+                    stack.extend(children.iter());
+                }
+            }
+        }
+    }
+}
+
+fn set_visible(q_visibility: &mut Query<&mut Visibility>, entity: Entity, visible: bool) {
+    if let Ok(mut visibility) = q_visibility.get_mut(entity) {
+        // Don't trigger change detection every frame
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+}
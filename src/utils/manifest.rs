@@ -0,0 +1,792 @@
+use bevy::asset::{AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Key of the asset path of the manifest to inherit from
+const EXTENDS_KEY: &str = "extends";
+/// Manifests sit next to their model, `models/pillar.gltf` is described by
+/// `models/pillar.manifest.json`
+pub const MANIFEST_EXTENSION: &str = "manifest.json";
+
+#[derive(Default)]
+pub struct ManifestLoader;
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Invalid manifest {path:?} at line {line}, column {column}: {source}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        source: serde_json::Error,
+    },
+    #[error("Couldn't read manifest {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Manifest {path:?} couldn't read its base {base:?}: {message}")]
+    Extends {
+        path: PathBuf,
+        base: PathBuf,
+        message: String,
+    },
+    #[error("Manifest {path:?} extends itself through {base:?}")]
+    Cycle { path: PathBuf, base: PathBuf },
+}
+
+#[derive(Deserialize, Default, Clone, TypeUuid)]
+#[uuid = "5e8ad52f-2a3c-4d3e-9a4b-7c1b0f6d2e91"]
+pub struct Manifest {
+    #[serde(default)]
+    pub transform: ManifestTransform,
+    #[serde(default)]
+    pub physics: Option<ManifestPhysics>,
+    #[serde(default)]
+    pub components: Vec<ManifestComponent>,
+    #[serde(default)]
+    pub material: Option<ManifestMaterial>,
+    /// Draw the model through `InstancingPlugin` instead of spawning its
+    /// scene. Material overrides and `SceneReady` don't apply then
+    #[serde(default)]
+    pub instanced: bool,
+    #[serde(default)]
+    pub lod: Option<ManifestLod>,
+    /// Manifest this one extends
+    #[serde(skip)]
+    pub extends: Option<ManifestBase>,
+    /// Fields of the whole chain merged, what manifests extending this one build on
+    #[serde(skip)]
+    resolved: Value,
+}
+
+/// Base of a manifest, merged in again by `update_extending_manifests` when it
+/// changes on disk
+#[derive(Clone)]
+pub struct ManifestBase {
+    pub handle: Handle<Manifest>,
+    /// Fields of the extending manifest alone
+    fields: Value,
+}
+
+/// Lower detail models swapped in with distance to the camera
+#[derive(Deserialize, Clone, Default)]
+pub struct ManifestLod {
+    #[serde(default)]
+    pub levels: Vec<ManifestLodLevel>,
+    /// Hide the model entirely beyond this distance
+    #[serde(default)]
+    pub max_distance: Option<f32>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ManifestLodLevel {
+    /// `gltf` asset path, e.g. `models/pillar_lod1.gltf`
+    pub model: String,
+    /// Distance from which this level replaces closer ones
+    pub distance: f32,
+}
+
+/// Material changes applied to the spawned scene, for the whole model and
+/// per glTF node name. Node entries override the model-wide ones
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ManifestMaterial {
+    #[serde(flatten)]
+    pub model: MaterialOverride,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub nodes: HashMap<String, MaterialOverride>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct MaterialOverride {
+    /// Name of another material of the same glTF to use instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap: Option<String>,
+    /// Multiplied with the base color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tint: Option<MColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emissive: Option<MColor>,
+    /// Multiplied with the emissive color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emissive_strength: Option<f32>,
+}
+
+/// Missing fields leave the model as it is, so bases can omit the transform
+#[derive(Deserialize, Clone)]
+pub struct ManifestTransform {
+    #[serde(default)]
+    pub translation: MVec3,
+    #[serde(default)]
+    pub rotation: ManifestRotation,
+    #[serde(default = "MVec3::one")]
+    pub scale: MVec3,
+}
+
+/// Extra component attached to the model's entity, e.g.
+/// `{ "type": "obstacle", "behaviour": { "kind": "static" } }`
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ManifestComponent {
+    /// Free-form tag, collected in `ManifestTags`
+    Tag { name: String },
+    /// Track obstacle, despawned once the ship has passed it
+    Obstacle {
+        #[serde(default)]
+        behaviour: ManifestObstacleBehaviour,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ManifestObstacleBehaviour {
+    Static,
+    /// Oscillates along x around its spawn position
+    Moving {
+        amplitude: f32,
+        period: f32,
+    },
+}
+
+/// Either `{ "euler": { "x": .., "y": .., "z": .. } }` in degrees, applied in
+/// YXZ order, or `{ "quat": { "x": .., "y": .., "z": .., "w": .. } }`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestRotation {
+    Euler(MVec3),
+    Quat(MQuat),
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ManifestPhysics {
+    #[serde(default)]
+    pub body: ManifestBodyType,
+    pub collider: ManifestCollider,
+    #[serde(default = "ManifestPhysics::default_friction")]
+    pub friction: f32,
+    #[serde(default)]
+    pub collision_groups: Option<ManifestCollisionGroups>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestBodyType {
+    Static,
+    Dynamic,
+    KinematicPositionBased,
+    KinematicVelocityBased,
+}
+
+/// Collider sizes are in model space, they get multiplied by the manifest scale
+#[derive(Deserialize, Clone, Copy)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum ManifestCollider {
+    Cuboid {
+        half_extents: MVec3,
+    },
+    Ball {
+        radius: f32,
+    },
+    /// Capsule along the y axis
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+    /// Built from the model's meshes once the `gltf` is loaded
+    ConvexHull,
+    /// Built from the model's meshes once the `gltf` is loaded
+    Trimesh,
+    /// Set of convex hulls approximating concave models, built from the
+    /// model's meshes once the `gltf` is loaded. Higher voxel `resolution` is
+    /// more precise but slower to build
+    ConvexDecomposition {
+        #[serde(default)]
+        resolution: Option<u32>,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct ManifestCollisionGroups {
+    pub memberships: u32,
+    pub filter: u32,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+pub struct MVec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct MQuat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct MColor {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    #[serde(default = "MColor::default_alpha")]
+    pub a: f32,
+}
+
+impl Manifest {
+    /// Read a manifest and every manifest it extends straight from disk, for
+    /// tools running without an asset server. Paths are relative to `root`
+    pub fn from_asset_path(root: &Path, path: &Path) -> Result<Self, ManifestError> {
+        let bytes = fs::read(root.join(path)).map_err(|source| ManifestError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut chain = ManifestChain::new(&bytes, path)?;
+        while let Some(base) = chain.next_base()? {
+            let bytes =
+                fs::read(root.join(&base)).map_err(|err| chain.extends_error(&base, err))?;
+            chain.push(&bytes, base)?;
+        }
+        chain.resolve()
+    }
+
+    fn from_resolved(resolved: Value) -> Result<Manifest, serde_json::Error> {
+        let mut manifest: Manifest = serde_json::from_value(resolved.clone())?;
+        manifest.resolved = resolved;
+        Ok(manifest)
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        Transform::from_translation(self.transform.translation.into())
+            .with_rotation(self.transform.rotation.into())
+            .with_scale(self.transform.scale.into())
+    }
+}
+
+impl AssetLoader for ManifestLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let (mut manifest, base) = async {
+                let mut chain = ManifestChain::new(bytes, load_context.path())?;
+                let base = chain.next_base()?;
+                let fields = chain.own_fields();
+                while let Some(base) = chain.next_base()? {
+                    let bytes = load_context
+                        .read_asset_bytes(&base)
+                        .await
+                        .map_err(|err| chain.extends_error(&base, err))?;
+                    chain.push(&bytes, base)?;
+                }
+                Ok::<_, ManifestError>((chain.resolve()?, base.map(|base| (base, fields))))
+            }
+            .await?;
+
+            let mut dependencies = Vec::new();
+            if let Some((base, fields)) = base {
+                let base = AssetPath::from(base);
+                manifest.extends = Some(ManifestBase {
+                    handle: load_context.get_handle(base.clone()),
+                    fields,
+                });
+                // Loading the base as well gets it watched for changes
+                dependencies.push(base);
+            }
+            load_context
+                .set_default_asset(LoadedAsset::new(manifest).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[MANIFEST_EXTENSION]
+    }
+}
+
+fn parse_error(path: &Path, err: serde_json::Error) -> ManifestError {
+    ManifestError::Parse {
+        path: path.to_path_buf(),
+        line: err.line(),
+        column: err.column(),
+        source: err,
+    }
+}
+
+/// Manifest followed by every manifest it extends, in order
+struct ManifestChain {
+    manifests: Vec<(PathBuf, Value)>,
+}
+
+impl ManifestChain {
+    fn new(bytes: &[u8], path: &Path) -> Result<Self, ManifestError> {
+        let mut chain = ManifestChain {
+            manifests: Vec::new(),
+        };
+        chain.push(bytes, path.to_path_buf())?;
+        Ok(chain)
+    }
+
+    fn push(&mut self, bytes: &[u8], path: PathBuf) -> Result<(), ManifestError> {
+        let value = serde_json::from_slice(bytes).map_err(|err| parse_error(&path, err))?;
+        self.manifests.push((path, value));
+        Ok(())
+    }
+
+    /// Asset path the last manifest of the chain extends
+    fn next_base(&self) -> Result<Option<PathBuf>, ManifestError> {
+        let (path, value) = self.manifests.last().unwrap();
+        let Some(base) = value.get(EXTENDS_KEY) else {
+            return Ok(None);
+        };
+        let Some(base) = base.as_str().map(PathBuf::from) else {
+            return Err(ManifestError::Extends {
+                path: path.clone(),
+                base: PathBuf::new(),
+                message: format!("`{}` must be an asset path", EXTENDS_KEY),
+            });
+        };
+
+        if self.manifests.iter().any(|(path, _)| *path == base) {
+            return Err(ManifestError::Cycle {
+                path: self.manifests[0].0.clone(),
+                base,
+            });
+        }
+        Ok(Some(base))
+    }
+
+    /// Fields of the first manifest of the chain, without the base it extends
+    fn own_fields(&self) -> Value {
+        let mut fields = self.manifests[0].1.clone();
+        if let Value::Object(fields) = &mut fields {
+            fields.remove(EXTENDS_KEY);
+        }
+        fields
+    }
+
+    fn extends_error(&self, base: &Path, err: impl ToString) -> ManifestError {
+        ManifestError::Extends {
+            path: self.manifests.last().unwrap().0.clone(),
+            base: base.to_path_buf(),
+            message: err.to_string(),
+        }
+    }
+
+    /// Merge the chain from the root base up, fields of a manifest override the
+    /// ones it extends
+    fn resolve(self) -> Result<Manifest, ManifestError> {
+        let path = self.manifests[0].0.clone();
+        let mut merged = Value::Null;
+        for (_, value) in self.manifests.into_iter().rev() {
+            merge(&mut merged, value);
+        }
+        if let Value::Object(fields) = &mut merged {
+            fields.remove(EXTENDS_KEY);
+        }
+
+        Manifest::from_resolved(merged).map_err(|err| parse_error(&path, err))
+    }
+}
+
+impl ManifestBase {
+    /// Extending manifest built on the current version of `base`
+    fn extend(&self, base: &Manifest) -> Result<Manifest, serde_json::Error> {
+        let mut resolved = base.resolved.clone();
+        merge(&mut resolved, self.fields.clone());
+        let mut manifest = Manifest::from_resolved(resolved)?;
+        manifest.extends = Some(self.clone());
+        Ok(manifest)
+    }
+}
+
+/// The asset server only reloads the file that changed, merge changed bases
+/// into the manifests extending them again. Those are modified in turn, so
+/// changes go down whole chains
+pub fn update_extending_manifests(
+    mut manifests: ResMut<Assets<Manifest>>,
+    mut manifest_events: EventReader<AssetEvent<Manifest>>,
+) {
+    let modified: Vec<Handle<Manifest>> = manifest_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone()),
+            _ => None,
+        })
+        .collect();
+    if modified.is_empty() {
+        return;
+    }
+
+    let extending: Vec<_> = manifests
+        .iter()
+        .filter_map(|(id, manifest)| {
+            let extends = manifest.extends.as_ref()?;
+            if !modified.contains(&extends.handle) {
+                return None;
+            }
+            match extends.extend(manifests.get(&extends.handle)?) {
+                Ok(manifest) => Some((id, manifest)),
+                Err(err) => {
+                    error!("Manifest {:?} no longer extends its base: {}", id, err);
+                    None
+                }
+            }
+        })
+        .collect();
+    for (id, manifest) in extending {
+        manifests.set_untracked(id, manifest);
+    }
+}
+
+/// Objects are merged key by key, any other value replaces the base one
+fn merge(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Object(base), Value::Object(fields)) => {
+            for (key, value) in fields {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+impl ManifestPhysics {
+    fn default_friction() -> f32 {
+        0.5
+    }
+
+    pub fn collider_bundle(&self, shape: ColliderShape) -> ColliderBundle {
+        let mut flags = ColliderFlags::default();
+        if let Some(groups) = self.collision_groups {
+            flags.collision_groups = InteractionGroups::new(groups.memberships, groups.filter);
+        }
+
+        ColliderBundle {
+            shape: shape.into(),
+            material: ColliderMaterial {
+                friction: self.friction,
+                ..Default::default()
+            }
+            .into(),
+            flags: flags.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl ManifestCollider {
+    /// Shape for primitive colliders, `None` for the ones built from meshes
+    pub fn primitive_shape(&self, scale: Vec3) -> Option<ColliderShape> {
+        match *self {
+            ManifestCollider::Cuboid { half_extents } => {
+                let half_extents = Vec3::from(half_extents) * scale;
+                Some(ColliderShape::cuboid(
+                    half_extents.x,
+                    half_extents.y,
+                    half_extents.z,
+                ))
+            }
+            ManifestCollider::Ball { radius } => {
+                Some(ColliderShape::ball(radius * scale.max_element()))
+            }
+            ManifestCollider::Capsule {
+                half_height,
+                radius,
+            } => {
+                let half_height = half_height * scale.y;
+                Some(ColliderShape::capsule(
+                    Point::new(0.0, -half_height, 0.0),
+                    Point::new(0.0, half_height, 0.0),
+                    radius * scale.x.max(scale.z),
+                ))
+            }
+            ManifestCollider::ConvexHull
+            | ManifestCollider::Trimesh
+            | ManifestCollider::ConvexDecomposition { .. } => None,
+        }
+    }
+}
+
+impl From<ManifestBodyType> for RigidBodyType {
+    fn from(body: ManifestBodyType) -> Self {
+        match body {
+            ManifestBodyType::Static => RigidBodyType::Static,
+            ManifestBodyType::Dynamic => RigidBodyType::Dynamic,
+            ManifestBodyType::KinematicPositionBased => RigidBodyType::KinematicPositionBased,
+            ManifestBodyType::KinematicVelocityBased => RigidBodyType::KinematicVelocityBased,
+        }
+    }
+}
+
+impl Default for ManifestTransform {
+    fn default() -> Self {
+        ManifestTransform {
+            translation: MVec3::default(),
+            rotation: ManifestRotation::default(),
+            scale: MVec3::one(),
+        }
+    }
+}
+
+impl Default for ManifestBodyType {
+    fn default() -> Self {
+        ManifestBodyType::Static
+    }
+}
+
+impl Default for ManifestObstacleBehaviour {
+    fn default() -> Self {
+        ManifestObstacleBehaviour::Static
+    }
+}
+
+impl Default for ManifestRotation {
+    fn default() -> Self {
+        ManifestRotation::Quat(MQuat {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        })
+    }
+}
+
+impl From<ManifestRotation> for Quat {
+    fn from(rotation: ManifestRotation) -> Self {
+        match rotation {
+            ManifestRotation::Euler(e) => Quat::from_euler(
+                EulerRot::YXZ,
+                e.y.to_radians(),
+                e.x.to_radians(),
+                e.z.to_radians(),
+            ),
+            ManifestRotation::Quat(q) => Quat::from_xyzw(q.x, q.y, q.z, q.w).normalize(),
+        }
+    }
+}
+
+impl ManifestMaterial {
+    /// Fields set in `other` replace the ones set here
+    pub fn overridden_by(&self, other: &ManifestMaterial) -> ManifestMaterial {
+        let mut nodes = self.nodes.clone();
+        for (name, node) in other.nodes.iter() {
+            let merged = match nodes.get(name) {
+                Some(base) => base.overridden_by(node),
+                None => node.clone(),
+            };
+            nodes.insert(name.clone(), merged);
+        }
+
+        ManifestMaterial {
+            model: self.model.overridden_by(&other.model),
+            nodes,
+        }
+    }
+
+    /// Changes for meshes under the node called `node`
+    pub fn for_node(&self, node: Option<&str>) -> MaterialOverride {
+        match node.and_then(|node| self.nodes.get(node)) {
+            Some(node) => self.model.overridden_by(node),
+            None => self.model.clone(),
+        }
+    }
+}
+
+impl MaterialOverride {
+    pub fn overridden_by(&self, other: &MaterialOverride) -> MaterialOverride {
+        MaterialOverride {
+            swap: other.swap.clone().or_else(|| self.swap.clone()),
+            tint: other.tint.or(self.tint),
+            emissive: other.emissive.or(self.emissive),
+            emissive_strength: other.emissive_strength.or(self.emissive_strength),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.swap.is_none()
+            && self.tint.is_none()
+            && self.emissive.is_none()
+            && self.emissive_strength.is_none()
+    }
+
+    /// Apply the changes to a copy of `material`
+    pub fn apply(&self, material: &StandardMaterial) -> StandardMaterial {
+        let mut material = material.clone();
+        if let Some(tint) = self.tint {
+            material.base_color = material.base_color * Vec4::from(Color::from(tint));
+        }
+        if let Some(emissive) = self.emissive {
+            material.emissive = emissive.into();
+        }
+        if let Some(strength) = self.emissive_strength {
+            material.emissive = material.emissive * strength;
+        }
+        material
+    }
+}
+
+impl MColor {
+    fn default_alpha() -> f32 {
+        1.0
+    }
+}
+
+impl From<MColor> for Color {
+    fn from(c: MColor) -> Self {
+        Color::rgba(c.r, c.g, c.b, c.a)
+    }
+}
+
+impl MVec3 {
+    fn one() -> Self {
+        Vec3::ONE.into()
+    }
+}
+
+impl From<MVec3> for Vec3 {
+    fn from(v: MVec3) -> Self {
+        Vec3::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Vec3> for MVec3 {
+    fn from(v: Vec3) -> Self {
+        MVec3 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"{
+        "transform": {
+            "translation": { "x": 1.0, "y": 2.0, "z": 3.0 },
+            "scale": { "x": 2.0, "y": 2.0, "z": 2.0 }
+        },
+        "instanced": true,
+        "material": { "tint": { "r": 1.0, "g": 0.0, "b": 0.0 } }
+    }"#;
+    const CHILD: &str = r#"{
+        "extends": "models/base.manifest.json",
+        "transform": { "translation": { "x": 5.0 } },
+        "material": { "emissive_strength": 2.0 }
+    }"#;
+
+    /// Resolve the first of `manifests`, bases are looked up by path among
+    /// the others
+    fn resolve(manifests: &[(&str, &str)]) -> Result<Manifest, ManifestError> {
+        let (path, json) = manifests[0];
+        let mut chain = ManifestChain::new(json.as_bytes(), Path::new(path))?;
+        while let Some(base) = chain.next_base()? {
+            let (_, json) = manifests
+                .iter()
+                .find(|(path, _)| base == Path::new(path))
+                .expect("base manifest missing from the test");
+            chain.push(json.as_bytes(), base)?;
+        }
+        chain.resolve()
+    }
+
+    #[test]
+    fn merges_base_fields() {
+        let manifest = resolve(&[
+            ("models/child.manifest.json", CHILD),
+            ("models/base.manifest.json", BASE),
+        ])
+        .unwrap();
+
+        // Objects are merged key by key, the child only moves along x
+        assert_eq!(
+            Vec3::from(manifest.transform.translation),
+            Vec3::new(5.0, 2.0, 3.0)
+        );
+        assert_eq!(Vec3::from(manifest.transform.scale), Vec3::splat(2.0));
+        assert!(manifest.instanced);
+
+        let material = manifest.material.unwrap();
+        assert_eq!(material.model.tint.unwrap().r, 1.0);
+        assert_eq!(material.model.emissive_strength, Some(2.0));
+        assert!(manifest.resolved.get(EXTENDS_KEY).is_none());
+    }
+
+    #[test]
+    fn merges_whole_chain() {
+        let grandchild = r#"{
+            "extends": "models/child.manifest.json",
+            "instanced": false
+        }"#;
+        let manifest = resolve(&[
+            ("models/grandchild.manifest.json", grandchild),
+            ("models/child.manifest.json", CHILD),
+            ("models/base.manifest.json", BASE),
+        ])
+        .unwrap();
+
+        assert!(!manifest.instanced);
+        assert_eq!(
+            Vec3::from(manifest.transform.translation),
+            Vec3::new(5.0, 2.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn extends_changed_base() {
+        let chain =
+            ManifestChain::new(CHILD.as_bytes(), Path::new("models/child.manifest.json")).unwrap();
+        let extends = ManifestBase {
+            handle: Handle::default(),
+            fields: chain.own_fields(),
+        };
+        let base = resolve(&[(
+            "models/base.manifest.json",
+            r#"{ "transform": { "translation": { "x": 0.0, "y": 7.0, "z": 0.0 } } }"#,
+        )])
+        .unwrap();
+
+        let manifest = extends.extend(&base).unwrap();
+        assert_eq!(
+            Vec3::from(manifest.transform.translation),
+            Vec3::new(5.0, 7.0, 0.0)
+        );
+        assert!(!manifest.instanced);
+        assert!(manifest.extends.is_some());
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let a = r#"{ "extends": "models/b.manifest.json" }"#;
+        let b = r#"{ "extends": "models/a.manifest.json" }"#;
+        let result = resolve(&[("models/a.manifest.json", a), ("models/b.manifest.json", b)]);
+        let Err(ManifestError::Cycle { path, base }) = result else {
+            panic!("cycle through `b` wasn't detected");
+        };
+        assert_eq!(path, Path::new("models/a.manifest.json"));
+        assert_eq!(base, Path::new("models/a.manifest.json"));
+
+        let a = r#"{ "extends": "models/a.manifest.json" }"#;
+        let result = resolve(&[("models/a.manifest.json", a)]);
+        assert!(matches!(result, Err(ManifestError::Cycle { .. })));
+    }
+
+    #[test]
+    fn rejects_invalid_extends() {
+        let result = resolve(&[("models/a.manifest.json", r#"{ "extends": 3 }"#)]);
+        assert!(matches!(result, Err(ManifestError::Extends { .. })));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::utils::manifest::{MColor, ManifestMaterial, MaterialOverride};
+use crate::utils::spawn::SceneReadyMarker;
+use bevy::asset::HandleId;
+use bevy::ecs::query::ChangeTrackers;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+/// Steps per unit override values are rounded to, closer values share a material
+const OVERRIDE_QUANTIZATION: f32 = 1024.0;
+
+/// Material changes for the scene spawned under this entity, applied once
+/// the scene is ready and again whenever they change
+#[derive(Component)]
+pub struct MaterialOverrides {
+    pub material: ManifestMaterial,
+    /// Model the swapped materials are looked up in
+    pub gltf: Handle<Gltf>,
+}
+
+/// Material a mesh had before overrides, so re-applying them doesn't stack
+#[derive(Component)]
+pub struct OriginalMaterial(Handle<StandardMaterial>);
+
+/// Overridden materials by base material and override, instances of a model
+/// share them so they still batch. Handles are weak, a material is dropped
+/// once no mesh uses it anymore
+#[derive(Default)]
+pub struct OverriddenMaterials(HashMap<(HandleId, OverrideKey), Handle<StandardMaterial>>);
+
+/// Values of a `MaterialOverride` changing the base material, the swap is
+/// already part of the base
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct OverrideKey {
+    tint: Option<[i32; 4]>,
+    emissive: Option<[i32; 4]>,
+    emissive_strength: Option<i32>,
+}
+
+impl OverrideKey {
+    fn new(material_override: &MaterialOverride) -> Self {
+        let color = |color: MColor| [color.r, color.g, color.b, color.a].map(quantize);
+        OverrideKey {
+            tint: material_override.tint.map(color),
+            emissive: material_override.emissive.map(color),
+            emissive_strength: material_override.emissive_strength.map(quantize),
+        }
+    }
+}
+
+fn quantize(value: f32) -> i32 {
+    (value * OVERRIDE_QUANTIZATION).round() as i32
+}
+
+#[allow(clippy::type_complexity)]
+pub fn apply_material_overrides(
+    mut commands: Commands,
+    gltfs: Res<Assets<Gltf>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut material_events: EventReader<AssetEvent<StandardMaterial>>,
+    mut overridden: Local<OverriddenMaterials>,
+    q_models: Query<
+        (
+            Entity,
+            &MaterialOverrides,
+            ChangeTrackers<MaterialOverrides>,
+            ChangeTrackers<SceneReadyMarker>,
+        ),
+        With<SceneReadyMarker>,
+    >,
+    q_children: Query<&Children>,
+    q_nodes: Query<(
+        Option<&Name>,
+        Option<&Handle<StandardMaterial>>,
+        Option<&OriginalMaterial>,
+    )>,
+) {
+    // Copies of a base material that changed are stale, meshes using them
+    // get a fresh copy below
+    let mut stale = HashSet::default();
+    for event in material_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                overridden.0.retain(|(base, _), _| *base != handle.id);
+                stale.insert(handle.id);
+            }
+            AssetEvent::Removed { handle } => {
+                overridden
+                    .0
+                    .retain(|(base, _), material| *base != handle.id && material != handle);
+            }
+            AssetEvent::Created { .. } => {}
+        }
+    }
+
+    for (entity, overrides, overrides_tracker, ready_tracker) in q_models.iter() {
+        let changed = overrides_tracker.is_changed() || ready_tracker.is_added();
+        if !changed && stale.is_empty() {
+            continue;
+        }
+
+        let gltf = gltfs.get(&overrides.gltf);
+        let mut stack: Vec<(Entity, Option<String>)> = vec![(entity, None)];
+
+        while let Some((entity, node)) = stack.pop() {
+            let Ok((name, handle, original)) = q_nodes.get(entity) else {
+                continue;
+            };
+            // Primitives are unnamed children of the node holding the mesh
+            let node = name.map(|name| name.as_str().to_string()).or(node);
+
+            if let Some(handle) = handle {
+                let original = match original {
+                    Some(original) => original.0.clone(),
+                    None => {
+                        commands
+                            .entity(entity)
+                            .insert(OriginalMaterial(handle.clone()));
+                        handle.clone()
+                    }
+                };
+
+                let material_override = overrides.material.for_node(node.as_deref());
+                let base = match (&material_override.swap, gltf) {
+                    (Some(swap), Some(gltf)) => match gltf.named_materials.get(swap) {
+                        Some(swapped) => swapped.clone(),
+                        None => {
+                            warn!("No material named `{}` to swap to", swap);
+                            original
+                        }
+                    },
+                    _ => original,
+                };
+
+                if changed || stale.contains(&base.id) {
+                    let new_handle = if material_override.is_empty() {
+                        base
+                    } else {
+                        let key = (base.id, OverrideKey::new(&material_override));
+                        match overridden.0.get(&key) {
+                            Some(handle) if materials.contains(handle) => {
+                                materials.get_handle(handle)
+                            }
+                            _ => match materials.get(&base) {
+                                Some(material) => {
+                                    let handle = materials.add(material_override.apply(material));
+                                    overridden.0.insert(key, handle.clone_weak());
+                                    handle
+                                }
+                                None => base,
+                            },
+                        }
+                    };
+                    commands.entity(entity).insert(new_handle);
+                }
+            }
+
+            if let Ok(children) = q_children.get(entity) {
+                stack.extend(children.iter().map(|child| (*child, node.clone())));
+            }
+        }
+    }
+}
+
+/// Put the original materials back on models whose manifest no longer has
+/// material changes. Runs after the stage removing `MaterialOverrides`
+pub fn restore_original_materials(
+    mut commands: Commands,
+    removed: RemovedComponents<MaterialOverrides>,
+    q_children: Query<&Children>,
+    q_originals: Query<&OriginalMaterial>,
+) {
+    for entity in removed.iter() {
+        let mut stack = vec![entity];
+        while let Some(entity) = stack.pop() {
+            if let Ok(original) = q_originals.get(entity) {
+                commands
+                    .entity(entity)
+                    .insert(original.0.clone())
+                    .remove::<OriginalMaterial>();
+            }
+            if let Ok(children) = q_children.get(entity) {
+                stack.extend(children.iter().copied());
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::utils::manifest::{ManifestCollider, ManifestPhysics};
+use crate::utils::scene_meshes::scene_meshes;
+use bevy::asset::HandleId;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::utils::HashMap;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::parry::transformation::vhacd::VHACDParameters;
+
+/// Collider waiting for the model's meshes to be loaded
+#[derive(Component)]
+pub struct PendingMeshCollider {
+    pub gltf: Handle<Gltf>,
+    pub physics: ManifestPhysics,
+    pub scale: Vec3,
+}
+
+/// Shapes built from a model's meshes, shared by every model of the same
+/// `gltf` spawned with the same collider and scale
+#[derive(Default)]
+pub struct MeshColliderCache {
+    shapes: HashMap<MeshColliderKey, ColliderShape>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct MeshColliderKey {
+    gltf: HandleId,
+    shape: MeshShape,
+    /// Bits of the scale, shapes are only reused for the exact same scale
+    scale: [u32; 3],
+}
+
+/// Colliders built from the model's meshes
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum MeshShape {
+    ConvexHull,
+    Trimesh,
+    ConvexDecomposition { resolution: Option<u32> },
+}
+
+/// Build colliders that need the model's meshes once its `gltf` is loaded,
+/// shapes already built for the same model are reused
+pub fn build_mesh_colliders(
+    mut commands: Commands,
+    mut cache: ResMut<MeshColliderCache>,
+    gltfs: Res<Assets<Gltf>>,
+    scenes: Res<Assets<Scene>>,
+    meshes: Res<Assets<Mesh>>,
+    mut gltf_events: EventReader<AssetEvent<Gltf>>,
+    q_pending: Query<(Entity, &PendingMeshCollider)>,
+) {
+    for event in gltf_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            cache.shapes.retain(|key, _| key.gltf != handle.id);
+        }
+    }
+
+    for (entity, pending) in q_pending.iter() {
+        let Some(shape) = MeshShape::from_collider(&pending.physics.collider) else {
+            commands.entity(entity).remove::<PendingMeshCollider>();
+            continue;
+        };
+        let key = MeshColliderKey {
+            gltf: pending.gltf.id,
+            shape,
+            scale: pending.scale.to_array().map(f32::to_bits),
+        };
+        if let Some(shape) = cache.shapes.get(&key) {
+            commands
+                .entity(entity)
+                .insert_bundle(pending.physics.collider_bundle(shape.clone()))
+                .remove::<PendingMeshCollider>();
+            continue;
+        }
+
+        let Some(scene_meshes) = gltfs
+            .get(&pending.gltf)
+            .and_then(|gltf| scene_meshes(gltf, &scenes))
+        else {
+            continue;
+        };
+
+        let primitives: Vec<(&Mesh, Mat4)> = scene_meshes
+            .iter()
+            .filter_map(|scene_mesh| {
+                let mesh = meshes.get(&scene_mesh.mesh)?;
+                Some((mesh, scene_mesh.transform))
+            })
+            .collect();
+
+        match mesh_shape(&pending.physics.collider, &primitives, pending.scale) {
+            Some(shape) => {
+                cache.shapes.insert(key, shape.clone());
+                commands
+                    .entity(entity)
+                    .insert_bundle(pending.physics.collider_bundle(shape));
+            }
+            None => error!("Couldn't build collider from meshes of {:?}", pending.gltf),
+        }
+        commands.entity(entity).remove::<PendingMeshCollider>();
+    }
+}
+
+impl MeshShape {
+    /// `None` for primitive colliders
+    fn from_collider(collider: &ManifestCollider) -> Option<Self> {
+        match *collider {
+            ManifestCollider::ConvexHull => Some(MeshShape::ConvexHull),
+            ManifestCollider::Trimesh => Some(MeshShape::Trimesh),
+            ManifestCollider::ConvexDecomposition { resolution } => {
+                Some(MeshShape::ConvexDecomposition { resolution })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Shape built from every mesh primitive of the model, each placed by its
+/// transform relative to the model root. `None` for primitive colliders
+pub fn mesh_shape(
+    collider: &ManifestCollider,
+    meshes: &[(&Mesh, Mat4)],
+    scale: Vec3,
+) -> Option<ColliderShape> {
+    let mut vertices: Vec<Point<Real>> = Vec::new();
+    let mut indices: Vec<[u32; 3]> = Vec::new();
+
+    for (mesh, transform) in meshes {
+        let transform = Mat4::from_scale(scale) * *transform;
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let offset = vertices.len() as u32;
+        vertices.extend(positions.iter().map(|p| {
+            let p = transform.transform_point3(Vec3::from(*p));
+            Point::new(p.x, p.y, p.z)
+        }));
+
+        let mesh_indices: Vec<u32> = match mesh.indices() {
+            Some(Indices::U16(i)) => i.iter().map(|i| *i as u32).collect(),
+            Some(Indices::U32(i)) => i.clone(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        indices.extend(
+            mesh_indices
+                .chunks_exact(3)
+                .map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]),
+        );
+    }
+
+    if vertices.is_empty() {
+        return None;
+    }
+
+    match collider {
+        ManifestCollider::ConvexHull => ColliderShape::convex_hull(&vertices),
+        ManifestCollider::Trimesh => Some(ColliderShape::trimesh(vertices, indices)),
+        ManifestCollider::ConvexDecomposition { resolution } => {
+            let mut params = VHACDParameters::default();
+            if let Some(resolution) = resolution {
+                params.resolution = *resolution;
+            }
+            Some(ColliderShape::convex_decomposition_with_params(
+                &vertices, &indices, &params,
+            ))
+        }
+        _ => None,
+    }
+}
@@ -8,8 +8,14 @@ use bevy::prelude::{
 };
 
 pub mod alter_transform_once;
+pub mod asset_validation;
 pub mod local_settings;
+pub mod lod;
 pub mod macros;
+pub mod manifest;
+pub mod material_override;
+pub mod mesh_collider;
+pub mod scene_meshes;
 pub mod spawn;
 
 pub fn rotate_camera_with_mouse(
@@ -18,17 +24,22 @@ pub fn rotate_camera_with_mouse(
     mut q_camera: Query<&mut Transform, With<MainCameraMarker>>,
     mut position: Local<Vec2>,
 ) {
-    let win = windows.get_primary().unwrap();
+    // The cursor is outside of the window or there is no window to rotate from
+    let Some(new_position) = windows.get_primary().and_then(|win| win.cursor_position()) else {
+        return;
+    };
+
     if mouse_button.just_pressed(MouseButton::Right) {
-        *position = win.cursor_position().unwrap();
+        *position = new_position;
     } else if mouse_button.pressed(MouseButton::Right) {
         let last_position = *position;
-        let new_position = win.cursor_position().unwrap();
         *position = new_position;
 
         let delta = new_position - last_position;
 
-        let mut camera = q_camera.single_mut();
+        let Ok(mut camera) = q_camera.get_single_mut() else {
+            return;
+        };
 
         camera.rotation =
             camera
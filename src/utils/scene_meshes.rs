@@ -0,0 +1,46 @@
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+
+/// Mesh primitive of a glTF scene, placed relative to the scene root
+pub struct SceneMesh {
+    pub mesh: Handle<Mesh>,
+    pub material: Option<Handle<StandardMaterial>>,
+    /// Product of every node transform from the scene root down to the primitive
+    pub transform: Mat4,
+}
+
+/// Every mesh primitive of the scene `spawn_model` spawns for `gltf`, the
+/// same hierarchy that gets rendered. `None` until the scene is loaded
+pub fn scene_meshes(gltf: &Gltf, scenes: &Assets<Scene>) -> Option<Vec<SceneMesh>> {
+    let scene = scenes.get(gltf.scenes.first()?)?;
+    let world = &scene.world;
+
+    let meshes = world
+        .archetypes()
+        .iter()
+        .flat_map(|archetype| archetype.entities())
+        .filter_map(|entity| {
+            let mesh = world.get::<Handle<Mesh>>(*entity)?;
+            Some(SceneMesh {
+                mesh: mesh.clone(),
+                material: world.get::<Handle<StandardMaterial>>(*entity).cloned(),
+                transform: root_transform(world, *entity),
+            })
+        })
+        .collect();
+    Some(meshes)
+}
+
+/// Transform of `entity` relative to the scene root, global transforms
+/// aren't propagated in scene worlds
+fn root_transform(world: &World, entity: Entity) -> Mat4 {
+    let mut transform = Mat4::IDENTITY;
+    let mut node = Some(entity);
+    while let Some(entity) = node {
+        if let Some(local) = world.get::<Transform>(entity) {
+            transform = local.compute_matrix() * transform;
+        }
+        node = world.get::<Parent>(entity).map(|parent| parent.0);
+    }
+    transform
+}
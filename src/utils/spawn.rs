@@ -1,53 +1,85 @@
-use crate::utils::alter_transform_once::AlterTransformOnce;
-use bevy::asset::AssetPath;
+use crate::materials::instancing::InstancedModel;
+use crate::utils::alter_transform_once::TweeningTransform;
+use crate::utils::lod::{update_lods, Lod};
+use crate::utils::manifest::{
+    update_extending_manifests, Manifest, ManifestBodyType, ManifestComponent, ManifestLoader,
+    ManifestObstacleBehaviour, ManifestPhysics, MANIFEST_EXTENSION,
+};
+use crate::utils::material_override::{
+    apply_material_overrides, restore_original_materials, MaterialOverrides,
+};
+use crate::utils::mesh_collider::{build_mesh_colliders, MeshColliderCache, PendingMeshCollider};
+use bevy::asset::{AssetPath, LoadState};
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
-use serde::Deserialize;
-use std::fs::File;
+use bevy::scene::InstanceId;
+use bevy::utils::HashSet;
+use bevy_easings::EasingComponent;
+use bevy_rapier3d::prelude::*;
 use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Default)]
-pub struct Manifest {
-    pub transform: ManifestTransform,
+pub struct SpawnPlugin;
+
+type ManifestOverride = Box<dyn Fn(&mut Manifest) + Send + Sync>;
+
+/// Model whose manifest is applied once loaded, and again whenever it changes on disk
+#[derive(Component)]
+pub struct ManifestInstance {
+    gltf_path: PathBuf,
+    /// Fails to load for models without a manifest file
+    manifest: Handle<Manifest>,
+    manifest_override: Option<ManifestOverride>,
+    spawned: bool,
 }
 
-#[derive(Deserialize, Default)]
-pub struct ManifestTransform {
-    pub translation: MVec3,
-    pub scale: MVec3,
+/// Transforms from the model's manifest, updated on reload
+#[derive(Component)]
+pub struct ManifestApplied {
+    /// Before overrides
+    pub base: Transform,
+    /// After overrides, the pose the model was placed at
+    pub transform: Transform,
 }
 
-#[derive(Deserialize, Default)]
-pub struct MVec3 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+/// Scene instance still being spawned by `SceneSpawner`
+#[derive(Component)]
+pub struct PendingScene {
+    instance: InstanceId,
 }
 
-impl Manifest {
-    fn from_path_or_default(manifest_path: &PathBuf) -> Self {
-        match File::open(PathBuf::from("assets").join(&manifest_path)) {
-            Ok(f) => serde_json::from_reader(f).unwrap(),
-            Err(e) => {
-                debug!("Couldn't find manifest file `{:?}`: {}", manifest_path, e);
-                Manifest::default()
-            }
-        }
-    }
+/// Added once the model's scene hierarchy exists, along with a `SceneReady` event
+#[derive(Component)]
+pub struct SceneReadyMarker;
 
-    fn to_alter_transform_once(&self) -> AlterTransformOnce {
-        AlterTransformOnce {
-            target: Transform::from_translation(Vec3::new(
-                self.transform.translation.x,
-                self.transform.translation.y,
-                self.transform.translation.z,
-            ))
-            .with_scale(Vec3::new(
-                self.transform.scale.x,
-                self.transform.scale.y,
-                self.transform.scale.z,
-            )),
-        }
+/// Sent once the scene spawned under `entity` has been instanced, its
+/// children can then be post-processed
+pub struct SceneReady {
+    pub entity: Entity,
+}
+
+/// Tags listed in the model's manifest
+#[derive(Component, Default)]
+pub struct ManifestTags(pub HashSet<String>);
+
+/// Components listed in the model's manifest, plugins owning the matching
+/// components insert them from `Added<ManifestComponents>`
+#[derive(Component)]
+pub struct ManifestComponents(pub Vec<ManifestComponent>);
+
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Manifest>()
+            .init_asset_loader::<ManifestLoader>()
+            .add_event::<SceneReady>()
+            .add_system(update_extending_manifests)
+            .add_system(apply_manifests)
+            .add_system(notify_scene_ready)
+            .add_system(apply_material_overrides)
+            .add_system_to_stage(CoreStage::PostUpdate, restore_original_materials)
+            .add_system(update_lods)
+            .init_resource::<MeshColliderCache>()
+            .add_system(build_mesh_colliders)
+            .add_system(drive_kinematic_bodies);
     }
 }
 
@@ -62,69 +94,320 @@ pub fn spawn_model_as_child(
 
     let ent = entity_commands.id();
 
-    scene_spawner.spawn_as_child(
+    let instance = scene_spawner.spawn_as_child(
         asset_server.load(AssetPath::new_ref(gltf_path, Some("Scene0"))),
         ent,
     );
+    entity_commands.insert(PendingScene { instance });
 
     ent
 }
 
-/// Spawn given `gltf` asset with optional manifest
+/// Spawn given `gltf` asset with optional manifest. The scene is instanced
+/// once the manifest is loaded
 pub fn spawn_model(
     path: impl AsRef<Path>,
     commands: &mut Commands,
-    scene_spawner: &mut SceneSpawner,
     asset_server: &AssetServer,
 ) -> Entity {
-    let gltf_path = path.as_ref();
-    let manifest_path = path.as_ref().with_extension("json");
-
-    let manifest = Manifest::from_path_or_default(&manifest_path);
-
-    spawn_with_manifest(commands, scene_spawner, asset_server, gltf_path, &manifest)
+    spawn_with_manifest(commands, asset_server, path.as_ref(), None)
 }
 
-/// Spawn given `gltf` asset with optional manifest and override function
-pub fn spawn_model_override<'a>(
+/// Spawn given `gltf` asset with optional manifest and override function. The
+/// override is run again whenever the manifest is reloaded
+pub fn spawn_model_override(
     path: impl AsRef<Path>,
-    commands: &'a mut Commands,
-    scene_spawner: &mut SceneSpawner,
+    commands: &mut Commands,
     asset_server: &AssetServer,
-    manifest_override: impl FnOnce(&mut Manifest),
+    manifest_override: impl Fn(&mut Manifest) + Send + Sync + 'static,
 ) -> Entity {
-    let gltf_path = path.as_ref();
-    let manifest_path = path.as_ref().with_extension("json");
-
-    let mut manifest = Manifest::from_path_or_default(&manifest_path);
-
-    manifest_override(&mut manifest);
-
-    spawn_with_manifest(commands, scene_spawner, asset_server, gltf_path, &manifest)
+    spawn_with_manifest(
+        commands,
+        asset_server,
+        path.as_ref(),
+        Some(Box::new(manifest_override)),
+    )
 }
 
 fn spawn_with_manifest(
     commands: &mut Commands,
-    scene_spawner: &mut SceneSpawner,
     asset_server: &AssetServer,
     gltf_path: &Path,
-    manifest: &Manifest,
+    manifest_override: Option<ManifestOverride>,
 ) -> Entity {
-    let alter_transform_once = manifest.to_alter_transform_once();
+    let manifest = asset_server.load(gltf_path.with_extension(MANIFEST_EXTENSION).as_path());
 
-    let ent = commands
+    commands
         .spawn()
         .insert_bundle((
             Transform::default(),
             GlobalTransform::default(),
-            alter_transform_once,
+            ManifestInstance {
+                gltf_path: gltf_path.to_path_buf(),
+                manifest,
+                manifest_override,
+                spawned: false,
+            },
         ))
-        .id();
+        .id()
+}
 
-    scene_spawner.spawn_as_child(
-        asset_server.load(AssetPath::new_ref(gltf_path, Some("Scene0"))),
-        ent,
-    );
+/// Apply manifests once they are loaded and again when they change. Reloads
+/// move models by the change of their manifest transform, keeping any motion
+/// on top of it, and rebuild their physics
+#[allow(clippy::type_complexity)]
+pub fn apply_manifests(
+    mut commands: Commands,
+    mut scene_spawner: ResMut<SceneSpawner>,
+    asset_server: Res<AssetServer>,
+    manifests: Res<Assets<Manifest>>,
+    mut manifest_events: EventReader<AssetEvent<Manifest>>,
+    mut q_instances: Query<(
+        Entity,
+        &mut ManifestInstance,
+        &Transform,
+        Option<&ManifestApplied>,
+        Option<&TweeningTransform>,
+    )>,
+) {
+    let modified: HashSet<Handle<Manifest>> = manifest_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone()),
+            _ => None,
+        })
+        .collect();
 
-    ent
+    for (entity, mut instance, transform, applied, tweening) in q_instances.iter_mut() {
+        if instance.spawned && !modified.contains(&instance.manifest) {
+            continue;
+        }
+
+        let mut manifest = match manifests.get(&instance.manifest) {
+            Some(manifest) => manifest.clone(),
+            // Manifests are optional, the asset server already reported
+            // invalid ones
+            None if asset_server.get_load_state(&instance.manifest) == LoadState::Failed => {
+                Manifest::default()
+            }
+            None => continue,
+        };
+
+        let base = manifest.to_transform();
+        if let Some(manifest_override) = &instance.manifest_override {
+            manifest_override(&mut manifest);
+        }
+        let manifest_transform = manifest.to_transform();
+
+        let mut entity_commands = commands.entity(entity);
+        let transform = match applied {
+            // Tweens would keep easing to the old pose, end them at the new one
+            Some(_) if tweening.is_some() => {
+                entity_commands.remove::<EasingComponent<Transform>>();
+                manifest_transform
+            }
+            Some(applied) => reapply(transform, &applied.transform, &manifest_transform),
+            None => manifest_transform,
+        };
+        entity_commands.insert_bundle((
+            transform,
+            ManifestApplied {
+                base,
+                transform: manifest_transform,
+            },
+        ));
+        match &manifest.material {
+            Some(material) => {
+                entity_commands.insert(MaterialOverrides {
+                    material: material.clone(),
+                    gltf: asset_server.load(instance.gltf_path.as_path()),
+                });
+            }
+            None if applied.is_some() => {
+                entity_commands.remove::<MaterialOverrides>();
+            }
+            None => {}
+        }
+
+        if instance.spawned {
+            // Rebuilt from scratch, the body type or collider may have changed
+            entity_commands
+                .remove_bundle::<RigidBodyBundle>()
+                .remove_bundle::<ColliderBundle>()
+                .remove::<RigidBodyPositionSync>()
+                .remove::<PendingMeshCollider>();
+        }
+        if let Some(physics) = &manifest.physics {
+            let mut physics = physics.clone();
+            if moves(&manifest) {
+                physics.body = ManifestBodyType::KinematicPositionBased;
+            }
+            insert_physics(
+                &mut entity_commands,
+                asset_server.as_ref(),
+                &instance.gltf_path,
+                &transform,
+                &physics,
+            );
+        }
+
+        if !instance.spawned {
+            insert_components(&mut entity_commands, &manifest);
+
+            if manifest.instanced {
+                entity_commands.insert_bundle((
+                    InstancedModel {
+                        gltf: asset_server.load(instance.gltf_path.as_path()),
+                    },
+                    Visibility::default(),
+                ));
+            } else {
+                let scene_instance = scene_spawner.spawn_as_child(
+                    asset_server.load(AssetPath::new_ref(&instance.gltf_path, Some("Scene0"))),
+                    entity,
+                );
+                entity_commands.insert(PendingScene {
+                    instance: scene_instance,
+                });
+            }
+
+            if let Some(lod) = &manifest.lod {
+                let lod = Lod::new(
+                    asset_server.load(instance.gltf_path.as_path()),
+                    lod,
+                    asset_server.as_ref(),
+                );
+                if !manifest.instanced {
+                    lod.spawn_levels(
+                        &mut commands,
+                        scene_spawner.as_mut(),
+                        asset_server.as_ref(),
+                        entity,
+                    );
+                }
+                commands.entity(entity).insert(lod);
+            }
+            instance.spawned = true;
+        }
+    }
+}
+
+/// Mark models whose scene has been instanced and notify about them
+pub fn notify_scene_ready(
+    mut commands: Commands,
+    scene_spawner: Res<SceneSpawner>,
+    mut scene_ready: EventWriter<SceneReady>,
+    q_pending: Query<(Entity, &PendingScene)>,
+) {
+    for (entity, pending) in q_pending.iter() {
+        if !scene_spawner.instance_is_ready(pending.instance) {
+            continue;
+        }
+
+        commands
+            .entity(entity)
+            .remove::<PendingScene>()
+            .insert(SceneReadyMarker);
+        scene_ready.send(SceneReady { entity });
+    }
+}
+
+/// Move `transform` by the change from the `old` to the `new` manifest transform
+fn reapply(transform: &Transform, old: &Transform, new: &Transform) -> Transform {
+    let scale = if old.scale.cmpne(Vec3::ZERO).all() {
+        transform.scale * new.scale / old.scale
+    } else {
+        new.scale
+    };
+
+    Transform {
+        translation: transform.translation + new.translation - old.translation,
+        rotation: new.rotation * old.rotation.inverse() * transform.rotation,
+        scale,
+    }
+}
+
+/// Body placed at `transform`, colliders sized by its scale
+fn insert_physics(
+    entity_commands: &mut EntityCommands,
+    asset_server: &AssetServer,
+    gltf_path: &Path,
+    transform: &Transform,
+    physics: &ManifestPhysics,
+) {
+    entity_commands.insert_bundle(RigidBodyBundle {
+        body_type: RigidBodyType::from(physics.body).into(),
+        position: (transform.translation, transform.rotation).into(),
+        ..Default::default()
+    });
+    // Kinematic position based bodies follow the transform through `drive_kinematic_bodies`
+    if matches!(
+        physics.body,
+        ManifestBodyType::Dynamic | ManifestBodyType::KinematicVelocityBased
+    ) {
+        entity_commands.insert(RigidBodyPositionSync::Discrete);
+    }
+
+    match physics.collider.primitive_shape(transform.scale) {
+        Some(shape) => {
+            entity_commands.insert_bundle(physics.collider_bundle(shape));
+        }
+        None => {
+            // Collider is inserted by `build_mesh_colliders` once the meshes are loaded
+            entity_commands.insert(PendingMeshCollider {
+                gltf: asset_server.load(gltf_path),
+                physics: physics.clone(),
+                scale: transform.scale,
+            });
+        }
+    }
+}
+
+/// Whether the model's components move it around, its body then has to follow
+fn moves(manifest: &Manifest) -> bool {
+    manifest.components.iter().any(|component| {
+        matches!(
+            component,
+            ManifestComponent::Obstacle {
+                behaviour: ManifestObstacleBehaviour::Moving { .. }
+            }
+        )
+    })
+}
+
+/// Move kinematic bodies to their transform on the next physics step
+pub fn drive_kinematic_bodies(
+    mut q_bodies: Query<
+        (
+            &Transform,
+            &RigidBodyTypeComponent,
+            &mut RigidBodyPositionComponent,
+        ),
+        Changed<Transform>,
+    >,
+) {
+    for (transform, body_type, mut position) in q_bodies.iter_mut() {
+        if body_type.0 != RigidBodyType::KinematicPositionBased {
+            continue;
+        }
+        let target: RigidBodyPositionComponent = (transform.translation, transform.rotation).into();
+        position.next_position = target.position;
+    }
+}
+
+fn insert_components(entity_commands: &mut EntityCommands, manifest: &Manifest) {
+    let mut tags = ManifestTags::default();
+
+    for component in manifest.components.iter() {
+        if let ManifestComponent::Tag { name } = component {
+            tags.0.insert(name.clone());
+        }
+    }
+
+    if !tags.0.is_empty() {
+        entity_commands.insert(tags);
+    }
+    if !manifest.components.is_empty() {
+        entity_commands.insert(ManifestComponents(manifest.components.clone()));
+    }
 }